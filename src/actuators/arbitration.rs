@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::io::RelaySwitchState;
+
+/// How much say a request has over a relay, later variants win over earlier ones
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+pub enum Priority {
+    Default,
+    Schedule,
+    Manual,
+    Safety,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum RequestSource {
+    Startup,
+    LightControl,
+    ClimateControl,
+    Watering,
+    Watchdog,
+    Http,
+    Cli,
+    /// The configuration changed
+    Config,
+    /// The panic hook or the shutdown signal handler
+    Safety,
+}
+
+/// A wish for a relay to be in a given state, together with who made it and why
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RelayRequest {
+    pub state: RelaySwitchState,
+    pub priority: Priority,
+    pub source: RequestSource,
+    pub reason: String,
+    #[serde(with = "chrono::serde::ts_seconds_option")]
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl RelayRequest {
+    pub fn new(
+        state: RelaySwitchState,
+        priority: Priority,
+        source: RequestSource,
+        reason: impl Into<String>,
+    ) -> RelayRequest {
+        RelayRequest {
+            state,
+            priority,
+            source,
+            reason: reason.into(),
+            expires: None,
+        }
+    }
+
+    pub fn until(self, expires: DateTime<Utc>) -> RelayRequest {
+        RelayRequest {
+            expires: Some(expires),
+            ..self
+        }
+    }
+
+    fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.expires.is_none_or(|expires| now < expires)
+    }
+}
+
+/// Keeps one request per relay and priority, the highest active one decides the relay's state
+#[derive(Default)]
+pub struct RelayArbiter {
+    requests: BTreeMap<(u8, Priority), RelayRequest>,
+}
+
+impl RelayArbiter {
+    /// Files `request`, replacing an earlier request for `pin` with the same priority
+    pub fn request(&mut self, pin: u8, request: RelayRequest) {
+        self.requests.insert((pin, request.priority), request);
+    }
+
+    pub fn release(&mut self, pin: u8, priority: Priority) -> Option<RelayRequest> {
+        self.requests.remove(&(pin, priority))
+    }
+
+    /// The request deciding the state of `pin`, relays nobody asked for stay off
+    pub fn owner(&self, pin: u8, now: DateTime<Utc>) -> RelayRequest {
+        self.requests
+            .range((pin, Priority::Default)..=(pin, Priority::Safety))
+            .map(|(_, request)| request)
+            .rfind(|request| request.is_active(now))
+            .cloned()
+            .unwrap_or_else(|| {
+                RelayRequest::new(
+                    RelaySwitchState::Off,
+                    Priority::Default,
+                    RequestSource::Startup,
+                    "No requests",
+                )
+            })
+    }
+
+    /// The active request filed for `pin` with exactly `priority`
+    pub fn get(&self, pin: u8, priority: Priority, now: DateTime<Utc>) -> Option<&RelayRequest> {
+        self.requests
+            .get(&(pin, priority))
+            .filter(|request| request.is_active(now))
+    }
+
+    /// Keeps only the requests for which `keep` returns true
+    pub fn retain(&mut self, mut keep: impl FnMut(u8, &RelayRequest) -> bool) {
+        self.requests
+            .retain(|(pin, _), request| keep(*pin, request));
+    }
+
+    /// Drops expired requests and returns the pins they were filed for
+    pub fn remove_expired(&mut self, now: DateTime<Utc>) -> Vec<u8> {
+        let mut pins = Vec::new();
+        self.requests.retain(|(pin, _), request| {
+            let active = request.is_active(now);
+            if !active && !pins.contains(pin) {
+                pins.push(*pin);
+            }
+            active
+        });
+        pins
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    #[test]
+    fn test_relay_arbiter() {
+        use RelaySwitchState::{Off, On};
+        let now = Utc::now();
+        let request = |state, priority, source| RelayRequest::new(state, priority, source, "");
+        let mut arbiter = RelayArbiter::default();
+        let owner = |arbiter: &RelayArbiter, now| {
+            let owner = arbiter.owner(0, now);
+            (owner.state, owner.source)
+        };
+
+        assert_eq!(owner(&arbiter, now), (Off, RequestSource::Startup));
+        arbiter.request(
+            0,
+            request(On, Priority::Schedule, RequestSource::LightControl),
+        );
+        assert_eq!(owner(&arbiter, now), (On, RequestSource::LightControl));
+
+        let manual = request(Off, Priority::Manual, RequestSource::Http);
+        arbiter.request(0, manual.until(now + TimeDelta::minutes(30)));
+        arbiter.request(
+            0,
+            request(On, Priority::Schedule, RequestSource::LightControl),
+        );
+        assert_eq!(owner(&arbiter, now), (Off, RequestSource::Http));
+        // Requests for other relays don't interfere
+        arbiter.request(1, request(On, Priority::Safety, RequestSource::Watchdog));
+        assert_eq!(owner(&arbiter, now), (Off, RequestSource::Http));
+
+        let later = now + TimeDelta::minutes(31);
+        assert_eq!(owner(&arbiter, later), (On, RequestSource::LightControl));
+        assert_eq!(arbiter.remove_expired(later), vec![0]);
+        assert!(arbiter.remove_expired(later).is_empty());
+
+        arbiter.release(0, Priority::Schedule);
+        assert_eq!(owner(&arbiter, now), (Off, RequestSource::Startup));
+    }
+}
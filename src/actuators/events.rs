@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+
+use super::{zone_devices, RequestSource};
+use crate::{io::RelaySwitchState, state::ProgramState};
+
+/// A relay switching to another state, and who made it switch
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct RelayEvent {
+    pub timestamp: i64,
+    pub channel: u8,
+    /// The zone of the device on the relay, if one is configured for it
+    pub zone: Option<String>,
+    pub device: Option<String>,
+    pub old_state: RelaySwitchState,
+    pub new_state: RelaySwitchState,
+    pub source: RequestSource,
+    pub reason: String,
+}
+
+impl RelayEvent {
+    pub fn new(
+        program_state: &ProgramState,
+        channel: u8,
+        old_state: RelaySwitchState,
+        new_state: RelaySwitchState,
+        source: RequestSource,
+        reason: impl Into<String>,
+    ) -> RelayEvent {
+        let (zone, device) = device_on(program_state, channel).unzip();
+        RelayEvent {
+            timestamp: program_state.clock.now().timestamp(),
+            channel,
+            zone,
+            device,
+            old_state,
+            new_state,
+            source,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// The zone and name of the device on `channel`
+fn device_on(program_state: &ProgramState, channel: u8) -> Option<(String, String)> {
+    program_state.zones.iter().find_map(|zone| {
+        zone_devices(&zone.config)
+            .into_iter()
+            .find(|device| device.relay == channel)
+            .map(|device| (zone.name.clone(), device.name))
+    })
+}
+
+/// Adds `event` to the storage, a relay that switched is not undone when this fails
+pub fn record_relay_event(program_state: &mut ProgramState, event: RelayEvent) {
+    if let Err(e) = program_state.storage.add_relay_event(&event) {
+        eprintln!("Could not record relay event: {:#}", e);
+    }
+}
+
+/// Records the relays on `pins` as switched off, e.g. by [`crate::io::Relay::all_off`]
+pub fn record_switched_off(
+    program_state: &mut ProgramState,
+    pins: &[u8],
+    source: RequestSource,
+    reason: &str,
+) {
+    for &pin in pins {
+        let event = RelayEvent::new(
+            program_state,
+            pin,
+            RelaySwitchState::On,
+            RelaySwitchState::Off,
+            source,
+            reason,
+        );
+        record_relay_event(program_state, event);
+    }
+}
@@ -0,0 +1,800 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{bail, Context};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Notify, task::JoinHandle};
+
+use crate::{
+    config::{Configuration, DeviceKind, DeviceSettings, StartupState, BUILT_IN_DEVICES},
+    history::{WateringReason, WateringRecord},
+    io::RelaySwitchState,
+    safety, sensors,
+    state::{ProgramState, ProgramStateShared},
+};
+
+mod arbitration;
+mod events;
+mod restore;
+
+pub use arbitration::{Priority, RelayArbiter, RelayRequest, RequestSource};
+use events::record_relay_event;
+pub use events::{record_switched_off, RelayEvent};
+use restore::SavedRelayState;
+pub use restore::SavedRelayStates;
+
+/// Files `request` for `pin` and switches the relay to the state that wins the arbitration
+pub fn request_relay(
+    pin: u8,
+    request: RelayRequest,
+    program_state: &mut ProgramState,
+) -> anyhow::Result<RelaySwitchState> {
+    program_state.arbiter.request(pin, request);
+    apply_arbitration(pin, program_state)
+}
+
+/// Withdraws the request with `priority` for `pin`, handing the relay to the next request in line
+pub fn release_relay(
+    pin: u8,
+    priority: Priority,
+    program_state: &mut ProgramState,
+) -> anyhow::Result<RelaySwitchState> {
+    program_state.arbiter.release(pin, priority);
+    apply_arbitration(pin, program_state)
+}
+
+/// Drops expired requests and switches their relays to whatever request is next in line
+pub fn expire_relay_requests(program_state: &mut ProgramState) -> anyhow::Result<()> {
+    let now = program_state.clock.now();
+    for pin in program_state.arbiter.remove_expired(now) {
+        apply_arbitration(pin, program_state)?;
+    }
+    Ok(())
+}
+
+/// Switches every relay to the state its requests ask for, after the relays were re-initialised
+pub fn reapply_relay_requests(program_state: &mut ProgramState) -> anyhow::Result<()> {
+    for pin in program_state.relay.configured_pins() {
+        apply_arbitration(pin, program_state)?;
+    }
+    Ok(())
+}
+
+fn apply_arbitration(
+    pin: u8,
+    program_state: &mut ProgramState,
+) -> anyhow::Result<RelaySwitchState> {
+    let owner = program_state.arbiter.owner(pin, program_state.clock.now());
+    let previous = program_state.relay.get_state(pin).ok();
+    program_state.relay.switch(pin, owner.state)?;
+    if previous != Some(owner.state) {
+        if let Err(e) = save_relay_states(program_state) {
+            eprintln!("Could not save relay states: {:#}", e);
+        }
+    }
+    if let Some(previous) = previous.filter(|previous| *previous != owner.state) {
+        let event = RelayEvent::new(
+            program_state,
+            pin,
+            previous,
+            owner.state,
+            owner.source,
+            owner.reason,
+        );
+        record_relay_event(program_state, event);
+    }
+    Ok(owner.state)
+}
+
+/// Switches every relay off outside of the arbitration, e.g. before they are rewired
+pub fn switch_all_off(
+    program_state: &mut ProgramState,
+    source: RequestSource,
+    reason: &str,
+) -> anyhow::Result<()> {
+    let on_pins = program_state.relay.all_off()?;
+    record_switched_off(program_state, &on_pins, source, reason);
+    Ok(())
+}
+
+/// The relay events of `zone` from `from` to `to`, both included, oldest first
+pub fn get_relay_events(
+    program_state: &ProgramState,
+    zone: usize,
+    from: i64,
+    to: i64,
+) -> anyhow::Result<Vec<RelayEvent>> {
+    let zone_name = &program_state.zones[zone].name;
+    let mut events = program_state.storage.relay_events(from, to)?;
+    events.retain(|event| event.zone.as_ref() == Some(zone_name));
+    Ok(events)
+}
+
+fn save_relay_states(program_state: &mut ProgramState) -> anyhow::Result<()> {
+    let relays = program_state
+        .relay
+        .configured_pins()
+        .into_iter()
+        .map(|pin| {
+            Ok(SavedRelayState {
+                pin,
+                state: program_state.relay.get_state(pin)?,
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+    SavedRelayStates { relays }.save(&program_state.paths.relay_states())
+}
+
+/// Switches every relay to its configured startup state
+///
+/// Relays set to restore their state get the one saved before the controller last stopped,
+/// if it stopped without switching the relays off.
+pub fn apply_startup_states(program_state: &mut ProgramState) -> anyhow::Result<()> {
+    let saved = SavedRelayStates::load(&program_state.paths.relay_states()).ok();
+    file_startup_requests(program_state, saved.as_ref());
+    reapply_relay_requests(program_state)
+}
+
+/// Files a request for every relay that doesn't start off
+pub fn file_startup_requests(program_state: &mut ProgramState, saved: Option<&SavedRelayStates>) {
+    let startup_states = program_state.config.relay_startup_states();
+    for pin in program_state.relay.configured_pins() {
+        let startup_state = startup_states.get(pin as usize).copied();
+        let (state, reason) = match startup_state.unwrap_or_default() {
+            StartupState::Off => continue,
+            StartupState::On => (RelaySwitchState::On, "Startup state"),
+            StartupState::Restore => match saved.and_then(|saved| saved.get(pin)) {
+                Some(state) => (state, "Restored after an unexpected restart"),
+                None => continue,
+            },
+        };
+        let request = RelayRequest::new(state, Priority::Default, RequestSource::Startup, reason);
+        program_state.arbiter.request(pin, request);
+    }
+}
+
+/// How long a relay switched by hand stays that way before the control loops take over again
+const MANUAL_SWITCH_DURATION: TimeDelta = TimeDelta::hours(1);
+
+/// A request for switching a relay by hand, which expires after a while
+pub fn manual_request(
+    state: RelaySwitchState,
+    source: RequestSource,
+    program_state: &ProgramState,
+) -> RelayRequest {
+    RelayRequest::new(state, Priority::Manual, source, "Switched by hand")
+        .until(program_state.clock.now() + MANUAL_SWITCH_DURATION)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RelayOwner {
+    pub pin: u8,
+    pub state: RelaySwitchState,
+    pub request: RelayRequest,
+}
+
+/// Who decided the state of every configured relay, and why
+pub fn get_relay_owners(program_state: &mut ProgramState) -> anyhow::Result<Vec<RelayOwner>> {
+    let now = program_state.clock.now();
+    program_state
+        .relay
+        .configured_pins()
+        .into_iter()
+        .map(|pin| {
+            Ok(RelayOwner {
+                pin,
+                state: program_state.relay.get_state(pin)?,
+                request: program_state.arbiter.owner(pin, now),
+            })
+        })
+        .collect()
+}
+
+/// The devices every zone has, switched by the control loops
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Device {
+    Lights,
+    Fan,
+    Pump,
+    Heater,
+}
+
+impl Device {
+    const ALL: [Device; 4] = [Device::Lights, Device::Fan, Device::Pump, Device::Heater];
+
+    pub fn name(self) -> &'static str {
+        BUILT_IN_DEVICES[self as usize]
+    }
+
+    fn kind(self) -> DeviceKind {
+        match self {
+            Device::Lights => DeviceKind::Light,
+            Device::Fan => DeviceKind::Fan,
+            Device::Pump => DeviceKind::Pump,
+            Device::Heater => DeviceKind::Heater,
+        }
+    }
+
+    fn pin(self, config: &Configuration) -> Option<u8> {
+        let relay_settings = &config.relay_settings;
+        match self {
+            Device::Lights => Some(relay_settings.light_pin),
+            Device::Fan => Some(relay_settings.fan_pin),
+            Device::Pump => Some(relay_settings.water_pump_pin),
+            Device::Heater => relay_settings.heater_pin,
+        }
+    }
+}
+
+/// The lights, fan, pump and heater followed by the devices configured for the zone
+pub fn zone_devices(config: &Configuration) -> Vec<DeviceSettings> {
+    let built_in = Device::ALL.into_iter().filter_map(|device| {
+        Some(DeviceSettings {
+            name: device.name().to_string(),
+            kind: device.kind(),
+            relay: device.pin(config)?,
+            polarity: None,
+            startup_state: None,
+            zone: None,
+            max_on_secs: (device == Device::Pump)
+                .then_some(config.water_pump_settings.max_run_secs),
+        })
+    });
+    built_in.chain(config.devices.iter().cloned()).collect()
+}
+
+/// The device of `zone` called `name`, if there is one
+pub fn find_device(
+    program_state: &ProgramState,
+    zone: usize,
+    name: &str,
+) -> Option<DeviceSettings> {
+    zone_devices(&program_state.zones[zone].config)
+        .into_iter()
+        .find(|device| device.name == name)
+}
+
+fn named_device(
+    program_state: &ProgramState,
+    zone: usize,
+    name: &str,
+) -> anyhow::Result<DeviceSettings> {
+    find_device(program_state, zone, name).with_context(|| format!("No device called {}", name))
+}
+
+/// How long an override of `minutes` lasts, refusing durations that are not positive or too long
+pub fn override_duration(minutes: i64) -> anyhow::Result<TimeDelta> {
+    if minutes <= 0 {
+        bail!("An override has to last at least a minute");
+    }
+    TimeDelta::try_minutes(minutes).context("Override is too long")
+}
+
+/// Holds device `name` of `zone` in `state` for `duration`, or until the override is cleared
+pub fn set_override(
+    zone: usize,
+    name: &str,
+    state: RelaySwitchState,
+    duration: Option<TimeDelta>,
+    source: RequestSource,
+    program_state: &mut ProgramState,
+) -> anyhow::Result<RelaySwitchState> {
+    let is_pump = name == Device::Pump.name();
+    if is_pump && state == RelaySwitchState::On {
+        bail!("The pump can only be held off, use watering to run it");
+    }
+    let device = named_device(program_state, zone, name)?;
+    let request = RelayRequest::new(state, Priority::Manual, source, "Override");
+    let request = match duration {
+        Some(duration) => {
+            let expires = program_state
+                .clock
+                .now()
+                .checked_add_signed(duration)
+                .context("Override is too long")?;
+            request.until(expires)
+        }
+        None => request,
+    };
+    if is_pump && program_state.zones[zone].pump_run.is_some() {
+        stop_pump(program_state, zone)?;
+    }
+    request_relay(device.relay, request, program_state)
+}
+
+pub fn clear_override(
+    zone: usize,
+    name: &str,
+    program_state: &mut ProgramState,
+) -> anyhow::Result<RelaySwitchState> {
+    let device = named_device(program_state, zone, name)?;
+    release_relay(device.relay, Priority::Manual, program_state)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ActiveOverride {
+    pub device: String,
+    pub request: RelayRequest,
+}
+
+pub fn get_overrides(program_state: &ProgramState, zone: usize) -> Vec<ActiveOverride> {
+    let now = program_state.clock.now();
+    zone_devices(&program_state.zones[zone].config)
+        .into_iter()
+        .filter_map(|device| {
+            let request = program_state
+                .arbiter
+                .get(device.relay, Priority::Manual, now)?;
+            Some(ActiveOverride {
+                device: device.name,
+                request: request.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Switches device `name` of `zone`, refusing to run the pump outside of watering
+pub fn switch_device(
+    zone: usize,
+    name: &str,
+    request: RelayRequest,
+    program_state: &mut ProgramState,
+) -> anyhow::Result<RelaySwitchState> {
+    if name == Device::Pump.name() && request.state == RelaySwitchState::On {
+        bail!("The pump can only be switched off, use watering to run it");
+    }
+    let device = named_device(program_state, zone, name)?;
+    request_relay(device.relay, request, program_state)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeviceState {
+    pub name: String,
+    pub kind: DeviceKind,
+    pub relay: u8,
+    pub state: RelaySwitchState,
+}
+
+pub fn get_device_states(
+    program_state: &mut ProgramState,
+    zone: usize,
+) -> anyhow::Result<Vec<DeviceState>> {
+    zone_devices(&program_state.zones[zone].config)
+        .into_iter()
+        .map(|device| {
+            Ok(DeviceState {
+                state: program_state.relay.get_state(device.relay)?,
+                name: device.name,
+                kind: device.kind,
+                relay: device.relay,
+            })
+        })
+        .collect()
+}
+
+pub fn switch_lights(
+    zone: usize,
+    request: RelayRequest,
+    program_state: &mut ProgramState,
+) -> anyhow::Result<RelaySwitchState> {
+    switch_built_in(zone, Device::Lights, request, program_state)
+}
+
+pub fn switch_fan(
+    zone: usize,
+    request: RelayRequest,
+    program_state: &mut ProgramState,
+) -> anyhow::Result<RelaySwitchState> {
+    switch_built_in(zone, Device::Fan, request, program_state)
+}
+
+pub fn switch_water_pump(
+    zone: usize,
+    request: RelayRequest,
+    program_state: &mut ProgramState,
+) -> anyhow::Result<RelaySwitchState> {
+    switch_built_in(zone, Device::Pump, request, program_state)
+}
+
+pub fn switch_heater(
+    zone: usize,
+    request: RelayRequest,
+    program_state: &mut ProgramState,
+) -> anyhow::Result<RelaySwitchState> {
+    switch_built_in(zone, Device::Heater, request, program_state)
+}
+
+fn switch_built_in(
+    zone: usize,
+    device: Device,
+    request: RelayRequest,
+    program_state: &mut ProgramState,
+) -> anyhow::Result<RelaySwitchState> {
+    let pin = device_pin(zone, device, program_state)?;
+    request_relay(pin, request, program_state)
+}
+
+fn device_pin(zone: usize, device: Device, program_state: &ProgramState) -> anyhow::Result<u8> {
+    device
+        .pin(&program_state.zones[zone].config)
+        .with_context(|| format!("No {:?} configured", device))
+}
+
+pub fn get_light_state(
+    program_state: &mut ProgramState,
+    zone: usize,
+) -> anyhow::Result<RelaySwitchState> {
+    let pin = device_pin(zone, Device::Lights, program_state)?;
+    program_state.relay.get_state(pin)
+}
+pub fn get_water_pump_state(
+    program_state: &mut ProgramState,
+    zone: usize,
+) -> anyhow::Result<RelaySwitchState> {
+    let pin = device_pin(zone, Device::Pump, program_state)?;
+    program_state.relay.get_state(pin)
+}
+pub fn get_fan_state(
+    program_state: &mut ProgramState,
+    zone: usize,
+) -> anyhow::Result<RelaySwitchState> {
+    let pin = device_pin(zone, Device::Fan, program_state)?;
+    program_state.relay.get_state(pin)
+}
+pub fn get_heater_state(
+    program_state: &mut ProgramState,
+    zone: usize,
+) -> anyhow::Result<RelaySwitchState> {
+    let pin = device_pin(zone, Device::Heater, program_state)?;
+    program_state.relay.get_state(pin)
+}
+
+pub type PumpRunHandle = JoinHandle<anyhow::Result<()>>;
+
+/// A pump run in progress, kept in the program state so it can be observed and cancelled
+pub struct PumpRun {
+    started: DateTime<Utc>,
+    duration: Duration,
+    target_grams: u64,
+    cancel: Arc<Notify>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PumpProgress {
+    pub elapsed_ms: u64,
+    pub duration_ms: u64,
+    pub grams_dispensed: f32,
+    pub target_grams: u64,
+}
+
+impl PumpRun {
+    pub fn progress(&self, now: DateTime<Utc>, grams_per_millisecond: f32) -> PumpProgress {
+        let elapsed_ms = (now - self.started)
+            .num_milliseconds()
+            .clamp(0, self.duration.as_millis() as i64) as u64;
+        PumpProgress {
+            elapsed_ms,
+            duration_ms: self.duration.as_millis() as u64,
+            grams_dispensed: elapsed_ms as f32 * grams_per_millisecond,
+            target_grams: self.target_grams,
+        }
+    }
+}
+
+pub fn get_pump_progress(program_state: &ProgramState, zone: usize) -> Option<PumpProgress> {
+    let zone = &program_state.zones[zone];
+    zone.pump_run.as_ref().map(|pump_run| {
+        pump_run.progress(
+            program_state.clock.now(),
+            zone.config.water_pump_settings.grams_per_millisecond,
+        )
+    })
+}
+
+/// Starts pumping `water_mass_g` in the background, the returned handle finishes with the pump run
+pub async fn pump_water(
+    zone: usize,
+    water_mass_g: u16,
+    reason: WateringReason,
+    program_state: &ProgramStateShared,
+) -> anyhow::Result<PumpRunHandle> {
+    let duration_ms = water_mass_g as f32
+        / program_state.lock().await.zones[zone]
+            .config
+            .water_pump_settings
+            .grams_per_millisecond;
+    let duration_ms = duration_ms.round() as u64;
+    pump_for(
+        zone,
+        Duration::from_millis(duration_ms),
+        reason,
+        program_state,
+    )
+    .await
+}
+
+/// Starts the pump for `duration` in the background, the returned handle finishes with the pump run
+pub async fn pump_for(
+    zone: usize,
+    duration: Duration,
+    reason: WateringReason,
+    program_state: &ProgramStateShared,
+) -> anyhow::Result<PumpRunHandle> {
+    let mut state = program_state.lock().await;
+    let zone_state = &state.zones[zone];
+    if zone_state.pump_run.is_some() {
+        bail!("Pump is already running");
+    }
+    let water_mass_g = (duration.as_millis() as f32
+        * zone_state.config.water_pump_settings.grams_per_millisecond)
+        .round() as u64;
+    if let Err(e) = safety::check_pump_request(
+        &zone_state.config.water_pump_settings,
+        &zone_state.history.watering_records,
+        state.clock.now(),
+        duration,
+        water_mass_g,
+    ) {
+        safety::log_intervention(&e.to_string());
+        return Err(e);
+    }
+
+    let moisture_before_watering = sensors::get_soil_moisture(&mut state, zone)?;
+    // Pump runs rank below manual requests, so a pump held off by an override stays off
+    let request = RelayRequest::new(
+        RelaySwitchState::On,
+        Priority::Schedule,
+        RequestSource::Watering,
+        format!("Watering {}g", water_mass_g),
+    );
+    if switch_water_pump(zone, request, &mut state)? != RelaySwitchState::On {
+        let pin = device_pin(zone, Device::Pump, &state)?;
+        let owner = state.arbiter.owner(pin, state.clock.now());
+        release_relay(pin, Priority::Schedule, &mut state)?;
+        bail!("Pump is held off: {}", owner.reason);
+    }
+    let cancel = Arc::new(Notify::new());
+    let started = state.clock.now();
+    state.zones[zone].pump_run = Some(PumpRun {
+        started,
+        duration,
+        target_grams: water_mass_g,
+        cancel: cancel.clone(),
+    });
+    let clock = state.clock.clone();
+    drop(state);
+
+    let program_state = program_state.clone();
+    Ok(tokio::spawn(async move {
+        tokio::select! {
+            _ = clock.sleep(duration) => (),
+            _ = cancel.notified() => (),
+        }
+        finish_pump_run(program_state, zone, moisture_before_watering, reason).await
+    }))
+}
+
+pub fn stop_pump(program_state: &mut ProgramState, zone: usize) -> anyhow::Result<()> {
+    let pump_run = program_state.zones[zone]
+        .pump_run
+        .as_ref()
+        .context("Pump is not running")?;
+    pump_run.cancel.notify_one();
+    Ok(())
+}
+
+async fn finish_pump_run(
+    program_state: ProgramStateShared,
+    zone: usize,
+    moisture_before_watering: f32,
+    reason: WateringReason,
+) -> anyhow::Result<()> {
+    let mut program_state = program_state.lock().await;
+    let progress = get_pump_progress(&program_state, zone).context("Pump run went missing")?;
+    program_state.zones[zone].pump_run = None;
+    let switched_off = device_pin(zone, Device::Pump, &program_state)
+        .and_then(|pin| release_relay(pin, Priority::Schedule, &mut program_state));
+
+    let record = WateringRecord::new(
+        program_state.clock.now(),
+        progress.grams_dispensed.round() as u64,
+        moisture_before_watering,
+        reason,
+    );
+    let program_state = &mut *program_state;
+    let zone = &mut program_state.zones[zone];
+    program_state
+        .storage
+        .add_watering_record(&zone.name, &record)?;
+    zone.history.watering_records.push(record);
+
+    switched_off.map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::ScaledClock,
+        config::{Configuration, IoBackend, Polarity, DEFAULT_ZONE},
+        history::History,
+        state::init_state,
+        testing::{TempDataDir, TestState},
+    };
+
+    #[tokio::test(start_paused = true)]
+    async fn test_overrides() {
+        let program_state = TestState::new("overrides", Configuration::default());
+        let mut state = program_state.lock().await;
+        state.zones[0].history = History::default();
+        use RelaySwitchState::{Off, On};
+
+        let schedule = RelayRequest::new(On, Priority::Schedule, RequestSource::LightControl, "");
+        switch_lights(0, schedule, &mut state).unwrap();
+        let thirty_minutes = Some(TimeDelta::minutes(30));
+        set_override(
+            0,
+            Device::Lights.name(),
+            Off,
+            thirty_minutes,
+            RequestSource::Cli,
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(get_light_state(&mut state, 0).unwrap(), Off);
+        // Durations from a query string or the CLI that would overflow the clock
+        assert!(override_duration(0).is_err());
+        assert!(override_duration(-5).is_err());
+        assert!(override_duration(999_999_999_999_999_999).is_err());
+        assert_eq!(override_duration(90).unwrap(), TimeDelta::minutes(90));
+        let forever = Some(TimeDelta::max_value());
+        assert!(set_override(0, "fan", On, forever, RequestSource::Http, &mut state).is_err());
+        assert!(set_override(0, "pump", On, None, RequestSource::Cli, &mut state).is_err());
+        assert!(set_override(0, "heater", Off, None, RequestSource::Cli, &mut state).is_err());
+        set_override(0, "pump", Off, None, RequestSource::Cli, &mut state).unwrap();
+        assert_eq!(get_overrides(&state, 0).len(), 2);
+        drop(state);
+
+        assert!(pump_water(0, 10, WateringReason::Manual, &program_state)
+            .await
+            .is_err());
+
+        tokio::time::advance(Duration::from_secs(31 * 60)).await;
+        let mut state = program_state.lock().await;
+        expire_relay_requests(&mut state).unwrap();
+        assert_eq!(get_light_state(&mut state, 0).unwrap(), On);
+        let overrides = get_overrides(&state, 0);
+        assert_eq!(overrides.len(), 1);
+        assert_eq!(overrides[0].device, "pump");
+        clear_override(0, "pump", &mut state).unwrap();
+        drop(state);
+
+        let pump_run = pump_water(0, 10, WateringReason::Manual, &program_state).await;
+        assert!(pump_run.unwrap().await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_relay_events() {
+        let mut config = Configuration::default();
+        config.io_settings.backend = IoBackend::Simulated;
+        let program_state = TestState::new("relay_events", config.clone());
+        let mut state = program_state.lock().await;
+        use RelaySwitchState::{Off, On};
+
+        let request = RelayRequest::new(
+            On,
+            Priority::Schedule,
+            RequestSource::ClimateControl,
+            "Too warm",
+        );
+        switch_fan(0, request.clone(), &mut state).unwrap();
+        // Nothing is recorded when the state stays the same
+        switch_fan(0, request, &mut state).unwrap();
+        let mut rewired = config.clone();
+        rewired.relay_settings.light_pin = 1;
+        rewired.relay_settings.fan_pin = 0;
+        crate::state::apply_config(&mut state, rewired).unwrap();
+        let manual = manual_request(On, RequestSource::Http, &state);
+        switch_fan(0, manual, &mut state).unwrap();
+        // The shutdown signal handler switches the relays off through a safety handle
+        let on_pins = state.relay.safety_handle().all_off().unwrap();
+        assert_eq!(on_pins, [0]);
+        record_switched_off(
+            &mut state,
+            &on_pins,
+            RequestSource::Safety,
+            "Received SIGTERM",
+        );
+        // Events of other zones are left out
+        let mut other_zone = RelayEvent::new(&state, 0, Off, On, RequestSource::Http, "");
+        other_zone.zone = Some("other".to_string());
+        state.storage.add_relay_event(&other_zone).unwrap();
+
+        let events = get_relay_events(&state, 0, i64::MIN, i64::MAX).unwrap();
+        let summary = events
+            .iter()
+            .map(|event| (event.device.as_deref(), event.new_state, event.source))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (Some("fan"), On, RequestSource::ClimateControl),
+                (Some("fan"), Off, RequestSource::Config),
+                (Some("fan"), On, RequestSource::Http),
+                (Some("fan"), Off, RequestSource::Safety),
+            ]
+        );
+        assert_eq!(events[0].old_state, Off);
+        assert_eq!(events[0].reason, "Too warm");
+        assert_eq!(events[0].zone.as_deref(), Some(DEFAULT_ZONE));
+    }
+
+    #[tokio::test]
+    async fn test_devices() {
+        let mut config = Configuration::default();
+        config.relay_settings.relay_gpio_pins = vec![17, 27, 22, 23];
+        config.devices = vec![DeviceSettings {
+            name: "humidifier".to_string(),
+            kind: DeviceKind::Other,
+            relay: 3,
+            polarity: Some(Polarity::ActiveHigh),
+            startup_state: Some(StartupState::On),
+            zone: None,
+            max_on_secs: Some(600),
+        }];
+        let program_state = TestState::new("devices", config);
+        let mut state = program_state.lock().await;
+        use RelaySwitchState::{Off, On};
+
+        assert!(find_device(&state, 0, "heater").is_none());
+        assert_eq!(state.relay.get_state(3).unwrap(), On);
+        let request = manual_request(On, RequestSource::Http, &state);
+        assert_eq!(
+            switch_device(0, "humidifier", request.clone(), &mut state).unwrap(),
+            On
+        );
+        assert!(switch_device(0, "pump", request, &mut state).is_err());
+
+        let devices = get_device_states(&mut state, 0).unwrap();
+        let names = devices.iter().map(|device| device.name.as_str());
+        assert_eq!(
+            names.collect::<Vec<_>>(),
+            ["lights", "fan", "pump", "humidifier"]
+        );
+        assert_eq!(devices[3].state, On);
+        assert_eq!(devices[2].state, Off);
+    }
+
+    #[tokio::test]
+    async fn test_startup_states() {
+        let paths = TempDataDir::new("startup_states");
+        let mut config = Configuration::default();
+        config.io_settings.backend = IoBackend::Simulated;
+        config.relay_settings.startup_states = vec![StartupState::Restore, StartupState::Restore];
+        let clock = Arc::new(ScaledClock::new(Utc::now(), 1.));
+        use RelaySwitchState::{Off, On};
+
+        // Left behind by a controller that stopped with the lights on and the fan off
+        let saved = SavedRelayStates {
+            relays: vec![
+                SavedRelayState { pin: 0, state: On },
+                SavedRelayState { pin: 1, state: Off },
+            ],
+        };
+        saved.save(&paths.relay_states()).unwrap();
+        let program_state = init_state(config.clone(), clock.clone(), paths.clone()).unwrap();
+        let mut state = program_state.lock().await;
+        assert_eq!(state.relay.get_state(0).unwrap(), On);
+        assert_eq!(state.relay.get_state(1).unwrap(), Off);
+        let request = RelayRequest::new(On, Priority::Schedule, RequestSource::ClimateControl, "");
+        switch_fan(0, request, &mut state).unwrap();
+        let saved = SavedRelayStates::load(&paths.relay_states()).unwrap();
+        assert_eq!(saved.get(1), Some(On));
+        drop(state);
+
+        // A clean shutdown leaves nothing to restore
+        std::fs::remove_file(paths.relay_states()).unwrap();
+        let program_state = init_state(config, clock, paths.clone()).unwrap();
+        assert_eq!(program_state.lock().await.relay.get_state(0).unwrap(), Off);
+    }
+}
@@ -0,0 +1,43 @@
+use std::{io::Write, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::io::RelaySwitchState;
+
+/// The state of every relay, saved whenever one switches so it can be restored after an
+/// unexpected restart. A clean shutdown removes the file.
+#[derive(Serialize, Deserialize, Default)]
+pub struct SavedRelayStates {
+    pub relays: Vec<SavedRelayState>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SavedRelayState {
+    pub pin: u8,
+    pub state: RelaySwitchState,
+}
+
+impl SavedRelayStates {
+    pub fn get(&self, pin: u8) -> Option<RelaySwitchState> {
+        self.relays
+            .iter()
+            .find(|relay| relay.pin == pin)
+            .map(|relay| relay.state)
+    }
+
+    /// Writes a temporary file and renames it over `path`, so a power cut never leaves half a file
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let text = toml::to_string_pretty(self)?;
+        let temp_path = path.with_extension("toml.tmp");
+        let mut file = std::fs::File::create(&temp_path)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<SavedRelayStates> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&text)?)
+    }
+}
@@ -0,0 +1,78 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::{config, paths::Paths};
+
+#[derive(Parser)]
+#[command(version, about = "Grow box controller")]
+pub struct Args {
+    /// Configuration file, created with the defaults if it doesn't exist
+    #[arg(long, global = true, default_value = config::FILE_PATH)]
+    pub config: PathBuf,
+
+    /// Directory for the watering history, data log, grow stage and camera images
+    #[arg(long, global = true, default_value = ".")]
+    pub data_dir: PathBuf,
+
+    /// Address the web interface listens on, instead of all interfaces on server_settings.port
+    #[arg(long, global = true)]
+    pub listen: Option<SocketAddr>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the controller with the web interface, the default
+    Serve,
+    /// Run the controller with an interactive prompt instead of the web interface
+    Cli,
+    /// Check the configuration file and exit
+    CheckConfig,
+    /// Print sensor voltages and readings to calibrate the sensors against
+    Calibrate {
+        /// Number of readings, one per second
+        #[arg(long, default_value_t = 10)]
+        samples: u32,
+    },
+    /// Write recorded data to standard output
+    Export {
+        data: ExportData,
+        /// Zone the data was recorded for
+        #[arg(long, default_value = config::DEFAULT_ZONE)]
+        zone: String,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExportData {
+    /// Watering history
+    History,
+    /// Temperature and soil moisture log
+    Datalog,
+    /// Readings of every sensor
+    Sensors,
+    /// When camera images were taken
+    Images,
+    /// Every relay switching, for all zones
+    RelayEvents,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl Args {
+    pub fn paths(&self) -> Paths {
+        Paths {
+            config: self.config.clone(),
+            data_dir: self.data_dir.clone(),
+        }
+    }
+}
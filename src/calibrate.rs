@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use crate::{sensors, state::ProgramStateShared};
+
+/// Prints raw sensor voltages next to what they convert to with the current settings
+///
+/// Holding the soil moisture sensor in water gives `voltage_100`, a reading in soil of known
+/// moisture gives `voltage_nominal` for `moisture_nominal`.
+pub async fn run_calibration(
+    program_state: ProgramStateShared,
+    samples: u32,
+) -> anyhow::Result<()> {
+    let zone_sensors = program_state
+        .lock()
+        .await
+        .zones
+        .iter()
+        .map(|zone| (zone.name.clone(), sensors::zone_sensors(&zone.config)))
+        .collect::<Vec<_>>();
+    let mut totals = zone_sensors
+        .iter()
+        .map(|(_, sensors)| vec![0.; sensors.len()])
+        .collect::<Vec<_>>();
+    for sample in 0..samples {
+        if sample > 0 {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        let mut program_state = program_state.lock().await;
+        let logic_level = program_state.config.board_settings.logic_level;
+        for ((zone, sensors), totals) in zone_sensors.iter().zip(&mut totals) {
+            for (sensor, total) in sensors.iter().zip(totals) {
+                let voltage = program_state.analog.read_voltage(sensor.pin)?;
+                println!(
+                    "{} {}: {:.3}V = {:.2}{}",
+                    zone,
+                    sensor.name,
+                    voltage,
+                    sensors::convert(&sensor.kind, logic_level, voltage),
+                    sensor.unit
+                );
+                *total += voltage;
+            }
+        }
+    }
+    if samples > 0 {
+        for ((zone, sensors), totals) in zone_sensors.iter().zip(totals) {
+            for (sensor, total) in sensors.iter().zip(totals) {
+                println!(
+                    "{} {} average: {:.3}V",
+                    zone,
+                    sensor.name,
+                    total / samples as f32
+                );
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,12 +1,14 @@
 use std::{thread, time::Duration};
 
 use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Local};
 use rustyline::{config::Configurer, error::ReadlineError, history::FileHistory};
 
 use crate::{
-    actuators,
-    io::{self, get_input_voltage},
-    sensors,
+    actuators::{self, RequestSource},
+    control, grow,
+    history::WateringReason,
+    io, sensors,
     state::ProgramStateShared,
 };
 
@@ -16,16 +18,25 @@ struct LoopFlags {
 
 async fn process_input(
     input: String,
+    zone: &mut usize,
     program_state: ProgramStateShared,
 ) -> anyhow::Result<LoopFlags> {
     let args = input.split(' ').collect::<Vec<_>>();
     let main_command = *args.first().context("No main command found.")?;
+    let zone_index = *zone;
     match main_command {
-        "ana" => command_ana(&args)?,
+        "ana" => command_ana(&args, program_state).await?,
         "rel" => command_rel(&args, program_state).await?,
-        "soil" => command_soil(&args, program_state).await?,
-        "temp" => command_temp(&args, program_state).await?,
-        "pump" => command_pump(&args, program_state).await?,
+        "dev" => command_dev(&args, zone_index, program_state).await?,
+        "soil" => command_soil(&args, zone_index, program_state).await?,
+        "temp" => command_temp(&args, zone_index, program_state).await?,
+        "sensors" => command_sensors(&args, zone_index, program_state).await?,
+        "pump" => command_pump(&args, zone_index, program_state).await?,
+        "next" => command_next(zone_index, program_state).await?,
+        "events" => command_events(&args, zone_index, program_state).await?,
+        "stage" => command_stage(&args, zone_index, program_state).await?,
+        "override" => command_override(&args, zone_index, program_state).await?,
+        "zone" => command_zone(&args, zone, program_state).await?,
         "exit" => return Ok(LoopFlags { exit: true }),
         _ => bail!("Unknown main command"),
     };
@@ -33,8 +44,48 @@ async fn process_input(
     Ok(LoopFlags { exit: false })
 }
 
-async fn command_pump(args: &[&str], program_state: ProgramStateShared) -> anyhow::Result<()> {
-    let mut program_state = program_state.lock().await;
+async fn command_zone(
+    args: &[&str],
+    zone: &mut usize,
+    program_state: ProgramStateShared,
+) -> anyhow::Result<()> {
+    let program_state = program_state.lock().await;
+    if let Some(name) = args.get(1).filter(|arg| !arg.is_empty()) {
+        *zone = program_state.zone_index(Some(name))?;
+    }
+    for (index, grow_zone) in program_state.zones.iter().enumerate() {
+        let marker = if index == *zone { "*" } else { " " };
+        println!("{} {}", marker, grow_zone.name);
+    }
+    Ok(())
+}
+
+async fn command_pump(
+    args: &[&str],
+    zone: usize,
+    program_state: ProgramStateShared,
+) -> anyhow::Result<()> {
+    match args.get(1).copied() {
+        Some("stop") => {
+            actuators::stop_pump(&mut *program_state.lock().await, zone)?;
+            println!("Stopping pump");
+            return Ok(());
+        }
+        Some("status") => {
+            match actuators::get_pump_progress(&*program_state.lock().await, zone) {
+                Some(progress) => println!(
+                    "Pumping: {}/{}ms, {:.0}/{}g",
+                    progress.elapsed_ms,
+                    progress.duration_ms,
+                    progress.grams_dispensed,
+                    progress.target_grams
+                ),
+                None => println!("Pump is not running"),
+            }
+            return Ok(());
+        }
+        _ => (),
+    }
 
     let use_grams = args
         .get(2)
@@ -43,27 +94,173 @@ async fn command_pump(args: &[&str], program_state: ProgramStateShared) -> anyho
 
     if use_grams {
         let grams: u16 = args.get(1).context("No mass specified.")?.parse()?;
-        actuators::pump_water(grams, &mut program_state)?;
+        actuators::pump_water(zone, grams, WateringReason::Manual, &program_state).await?;
+        println!("Pump started");
         return Ok(());
     }
 
     let duration_ms: u64 = args.get(1).context("No duration specified.")?.parse()?;
     let duration = Duration::from_millis(duration_ms);
-    actuators::switch_water_pump(io::RelaySwitchState::On, &mut program_state)?;
-    thread::sleep(duration);
-    actuators::switch_water_pump(io::RelaySwitchState::Off, &mut program_state)?;
+    actuators::pump_for(zone, duration, WateringReason::Manual, &program_state).await?;
+    println!("Pump started");
+
+    Ok(())
+}
+
+async fn command_override(
+    args: &[&str],
+    zone: usize,
+    program_state: ProgramStateShared,
+) -> anyhow::Result<()> {
+    let mut program_state = program_state.lock().await;
+
+    let Some(device) = args.get(1).filter(|arg| !arg.is_empty()) else {
+        let overrides = actuators::get_overrides(&program_state, zone);
+        if overrides.is_empty() {
+            println!("No active overrides");
+        }
+        for active_override in overrides {
+            let request = active_override.request;
+            let expires = request
+                .expires
+                .map(|time| format!("until {}", time.with_timezone(&Local).format("%H:%M")))
+                .unwrap_or("until cleared".to_string());
+            println!(
+                "{}: {:?} {}",
+                active_override.device, request.state, expires
+            );
+        }
+        return Ok(());
+    };
+
+    let state = match args.get(2).copied() {
+        Some("clear") => {
+            actuators::clear_override(zone, device, &mut program_state)?;
+            println!("Override cleared");
+            return Ok(());
+        }
+        Some("on") => io::RelaySwitchState::On,
+        Some("off") => io::RelaySwitchState::Off,
+        _ => bail!("Must specify on, off or clear"),
+    };
+    let duration = args
+        .get(3)
+        .map(|minutes| minutes.parse().context("Not a valid number of minutes"))
+        .transpose()?
+        .map(actuators::override_duration)
+        .transpose()?;
+    actuators::set_override(
+        zone,
+        device,
+        state,
+        duration,
+        RequestSource::Cli,
+        &mut program_state,
+    )?;
+    println!("Override set");
+    Ok(())
+}
+
+async fn command_stage(
+    args: &[&str],
+    zone: usize,
+    program_state: ProgramStateShared,
+) -> anyhow::Result<()> {
+    let mut program_state = program_state.lock().await;
+    if args.get(1).is_some_and(|arg| *arg == "advance") {
+        grow::advance_stage(&mut program_state, zone)?;
+    }
+    match grow::get_stage_progress(&program_state, zone) {
+        Some(progress) => {
+            let stage_ends = progress
+                .stage_ends
+                .and_then(|time| DateTime::from_timestamp(time, 0))
+                .map(|time| {
+                    let time = time.with_timezone(&Local);
+                    format!("ends {}", time.format("%Y-%m-%d %H:%M"))
+                })
+                .unwrap_or("lasts until advanced".to_string());
+            println!(
+                "{}: stage {}/{} {}, day {:.1}, {}",
+                progress.profile,
+                progress.stage + 1,
+                progress.stage_count,
+                progress.stage_name,
+                progress.days_in_stage,
+                stage_ends
+            );
+        }
+        None => println!("No grow profile active"),
+    }
+    Ok(())
+}
+
+async fn command_events(
+    args: &[&str],
+    zone: usize,
+    program_state: ProgramStateShared,
+) -> anyhow::Result<()> {
+    let count: usize = match args.get(1).filter(|arg| !arg.is_empty()) {
+        Some(count) => count.parse().context("Not a valid number of events")?,
+        None => 20,
+    };
+    let program_state = program_state.lock().await;
+    let events = actuators::get_relay_events(&program_state, zone, i64::MIN, i64::MAX)?;
+    if events.is_empty() {
+        println!("No relay events");
+    }
+    for event in events.iter().skip(events.len().saturating_sub(count)) {
+        let time = DateTime::from_timestamp(event.timestamp, 0)
+            .context("Invalid event time")?
+            .with_timezone(&Local);
+        let device = match (&event.zone, &event.device) {
+            (Some(zone), Some(device)) => format!(" ({} {})", zone, device),
+            _ => String::new(),
+        };
+        println!(
+            "{} relay {}{}: {:?} -> {:?}, {:?} {}",
+            time.format("%Y-%m-%d %H:%M:%S"),
+            event.channel,
+            device,
+            event.old_state,
+            event.new_state,
+            event.source,
+            event.reason
+        );
+    }
+    Ok(())
+}
 
+async fn command_next(zone: usize, program_state: ProgramStateShared) -> anyhow::Result<()> {
+    let program_state = program_state.lock().await;
+    match control::soil::get_next_watering(&program_state, zone) {
+        Some(watering) => {
+            let time = DateTime::from_timestamp(watering.time, 0)
+                .context("Invalid watering time")?
+                .with_timezone(&Local);
+            println!(
+                "Next watering: {}g at {}",
+                watering.amount,
+                time.format("%Y-%m-%d %H:%M")
+            );
+        }
+        None => println!("No watering scheduled, watering depends on soil moisture"),
+    }
     Ok(())
 }
 
-async fn command_temp(args: &[&str], program_state: ProgramStateShared) -> anyhow::Result<()> {
+async fn command_temp(
+    args: &[&str],
+    zone: usize,
+    program_state: ProgramStateShared,
+) -> anyhow::Result<()> {
     let show_loop = args
         .get(1)
         .map(|arg| matches!(*arg, "loop"))
         .unwrap_or(false);
     loop {
-        let program_state = program_state.lock().await;
-        let temperature = sensors::get_temperature(&program_state.config)?;
+        let mut program_state = program_state.lock().await;
+        let temperature = sensors::get_temperature(&mut program_state, zone)?;
         println!("Temperature: {}C", temperature);
         if !show_loop {
             break;
@@ -73,15 +270,19 @@ async fn command_temp(args: &[&str], program_state: ProgramStateShared) -> anyho
     Ok(())
 }
 
-async fn command_soil(args: &[&str], program_state: ProgramStateShared) -> anyhow::Result<()> {
+async fn command_soil(
+    args: &[&str],
+    zone: usize,
+    program_state: ProgramStateShared,
+) -> anyhow::Result<()> {
     let show_loop = args
         .get(1)
         .map(|arg| matches!(*arg, "loop"))
         .unwrap_or(false);
 
     loop {
-        let program_state = program_state.lock().await;
-        let humidity = sensors::get_soil_moisture(&program_state.config)?;
+        let mut program_state = program_state.lock().await;
+        let humidity = sensors::get_soil_moisture(&mut program_state, zone)?;
         println!("Soil humidity: {}", humidity);
         if !show_loop {
             break;
@@ -92,14 +293,75 @@ async fn command_soil(args: &[&str], program_state: ProgramStateShared) -> anyho
     Ok(())
 }
 
+async fn command_sensors(
+    args: &[&str],
+    zone: usize,
+    program_state: ProgramStateShared,
+) -> anyhow::Result<()> {
+    let show_loop = args
+        .get(1)
+        .map(|arg| matches!(*arg, "loop"))
+        .unwrap_or(false);
+
+    loop {
+        let mut program_state = program_state.lock().await;
+        for reading in sensors::read_sensors(&mut program_state, zone)? {
+            println!("{}: {}{}", reading.name, reading.value, reading.unit);
+        }
+        if !show_loop {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    Ok(())
+}
+
+async fn command_dev(
+    args: &[&str],
+    zone: usize,
+    program_state: ProgramStateShared,
+) -> anyhow::Result<()> {
+    let mut program_state = program_state.lock().await;
+
+    let Some(device) = args.get(1).filter(|arg| !arg.is_empty()) else {
+        for device in actuators::get_device_states(&mut program_state, zone)? {
+            println!(
+                "{} ({:?}, relay {}): {:?}",
+                device.name, device.kind, device.relay, device.state
+            );
+        }
+        return Ok(());
+    };
+    let state = match args.get(2).copied() {
+        Some("on") => io::RelaySwitchState::On,
+        Some("off") => io::RelaySwitchState::Off,
+        _ => bail!("Must specify on or off"),
+    };
+    let request = actuators::manual_request(state, RequestSource::Cli, &program_state);
+    actuators::switch_device(zone, device, request, &mut program_state)?;
+    println!("Switched {}", device);
+    Ok(())
+}
+
 async fn command_rel(args: &[&str], program_state: ProgramStateShared) -> anyhow::Result<()> {
     let mut program_state = program_state.lock().await;
 
-    let pin = args
-        .get(1)
-        .context("Must specify pin number.")?
-        .parse::<u8>()
-        .context("Not a valid pin number")?;
+    let Some(pin) = args.get(1).filter(|arg| !arg.is_empty()) else {
+        for owner in actuators::get_relay_owners(&mut program_state)? {
+            let request = owner.request;
+            let expires = request
+                .expires
+                .map(|time| format!(" until {}", time.with_timezone(&Local).format("%H:%M")))
+                .unwrap_or_default();
+            println!(
+                "Relay {}: {:?}, {:?} by {:?}{}: {}",
+                owner.pin, owner.state, request.priority, request.source, expires, request.reason
+            );
+        }
+        return Ok(());
+    };
+    let pin = pin.parse::<u8>().context("Not a valid pin number")?;
 
     let switch_state = args.get(2).map(|arg| match *arg {
         "1" => Ok(io::RelaySwitchState::On),
@@ -111,21 +373,26 @@ async fn command_rel(args: &[&str], program_state: ProgramStateShared) -> anyhow
         _ => Err(anyhow!("Not a valid switch state")),
     });
 
-    match switch_state {
+    let state = match switch_state {
         Some(state) => {
             println!("Switching relay");
-            program_state.relay.switch(pin, state?)?
+            state?
         }
         None => {
             println!("Toggling relay");
-            program_state.relay.toggle(pin)?
+            match program_state.relay.get_state(pin)? {
+                io::RelaySwitchState::On => io::RelaySwitchState::Off,
+                io::RelaySwitchState::Off => io::RelaySwitchState::On,
+            }
         }
     };
+    let request = actuators::manual_request(state, RequestSource::Cli, &program_state);
+    actuators::request_relay(pin, request, &mut program_state)?;
 
     Ok(())
 }
 
-fn command_ana(args: &[&str]) -> anyhow::Result<()> {
+async fn command_ana(args: &[&str], program_state: ProgramStateShared) -> anyhow::Result<()> {
     let pin = args
         .get(1)
         .context("Must specify pin number.")?
@@ -138,7 +405,7 @@ fn command_ana(args: &[&str]) -> anyhow::Result<()> {
         .unwrap_or(false);
 
     loop {
-        let voltage = get_input_voltage(pin)?;
+        let voltage = program_state.lock().await.analog.read_voltage(pin)?;
         println!("Voltage read: {}", voltage);
         if !show_loop {
             break;
@@ -151,14 +418,19 @@ fn command_ana(args: &[&str]) -> anyhow::Result<()> {
 
 async fn cli_loop(
     rl: &mut CLIEditor,
+    zone: &mut usize,
     program_state: ProgramStateShared,
 ) -> anyhow::Result<LoopFlags> {
-    let readline = rl.readline("growpi>> ");
+    let prompt = match *zone {
+        0 => "growpi>> ".to_string(),
+        zone => format!("growpi {}>> ", program_state.lock().await.zones[zone].name),
+    };
+    let readline = rl.readline(&prompt);
 
     match readline {
         Ok(line) => {
             rl.add_history_entry(line.as_str())?;
-            process_input(line, program_state).await
+            process_input(line, zone, program_state).await
         }
         Err(ReadlineError::Eof) => Ok(LoopFlags { exit: true }),
         Err(_) => Err(anyhow!("No input")),
@@ -174,9 +446,10 @@ fn init_readline() -> anyhow::Result<CLIEditor> {
 
 pub async fn run_cli(program_state: ProgramStateShared) {
     let mut rl = init_readline().unwrap();
+    let mut zone = 0;
 
     'cli_loop: loop {
-        match cli_loop(&mut rl, program_state.clone()).await {
+        match cli_loop(&mut rl, &mut zone, program_state.clone()).await {
             Ok(loop_flags) => {
                 if loop_flags.exit {
                     println!("Leaving CLI");
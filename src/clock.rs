@@ -0,0 +1,80 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, Local, NaiveTime, TimeDelta, TimeZone, Utc};
+
+use crate::{
+    config::{Configuration, IoBackend},
+    io::BoxFuture,
+};
+
+pub type SharedClock = Arc<dyn Clock>;
+
+/// Source of time for everything that schedules or timestamps.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+
+    fn local_now(&self) -> DateTime<Local> {
+        self.now().with_timezone(&Local)
+    }
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Runs `acceleration` times faster than the tokio clock, starting at `start_time`.
+///
+/// Since it is based on the tokio clock, a paused tokio runtime makes it fully deterministic.
+pub struct ScaledClock {
+    start_time: DateTime<Utc>,
+    started: tokio::time::Instant,
+    acceleration: f64,
+}
+
+impl ScaledClock {
+    pub fn new(start_time: DateTime<Utc>, acceleration: f64) -> ScaledClock {
+        ScaledClock {
+            start_time,
+            started: tokio::time::Instant::now(),
+            acceleration,
+        }
+    }
+}
+
+impl Clock for ScaledClock {
+    fn now(&self) -> DateTime<Utc> {
+        let elapsed = self.started.elapsed().mul_f64(self.acceleration);
+        self.start_time + TimeDelta::milliseconds(elapsed.as_millis() as i64)
+    }
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration.div_f64(self.acceleration)))
+    }
+}
+
+pub fn from_config(config: &Configuration) -> SharedClock {
+    match config.io_settings.backend {
+        IoBackend::Hardware => Arc::new(SystemClock),
+        IoBackend::Simulated => Arc::new(ScaledClock::new(
+            Utc::now(),
+            config.simulation_settings.time_acceleration.into(),
+        )),
+    }
+}
+
+/// `time` on the local day `days` after the one of `date`, `None` if a DST change skips it
+pub fn local_time_on_day(
+    date: DateTime<Local>,
+    time: NaiveTime,
+    days: i64,
+) -> Option<DateTime<Local>> {
+    let date = date.date_naive() + TimeDelta::days(days);
+    Local.from_local_datetime(&date.and_time(time)).earliest()
+}
@@ -0,0 +1,911 @@
+use std::io::Write;
+
+use anyhow::{bail, Context};
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+
+use crate::io::ImageResolution;
+
+mod reload;
+mod validation;
+
+pub use reload::watch_config_file;
+
+pub const FILE_PATH: &str = "./growpi.toml";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RelaySettings {
+    pub light_pin: u8,
+    pub fan_pin: u8,
+    pub water_pump_pin: u8,
+    #[serde(default)]
+    pub heater_pin: Option<u8>,
+    pub relay_gpio_pins: Vec<i16>,
+    /// Polarity of each relay in `relay_gpio_pins`, relays left out are active-low
+    #[serde(default)]
+    pub relay_polarities: Vec<Polarity>,
+    /// State of each relay in `relay_gpio_pins` when the controller starts, relays left out
+    /// start off
+    #[serde(default)]
+    pub startup_states: Vec<StartupState>,
+}
+
+/// Names of the devices on `light_pin`, `fan_pin`, `water_pump_pin` and `heater_pin`
+pub const BUILT_IN_DEVICES: [&str; 4] = ["lights", "fan", "pump", "heater"];
+
+/// Whether a relay switches on when its GPIO pin is driven low or high
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+pub enum Polarity {
+    #[default]
+    ActiveLow,
+    ActiveHigh,
+}
+
+/// What a relay does when the controller starts, until a control loop or request decides
+/// otherwise. Relays also fall back to it once all requests for them are gone.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+pub enum StartupState {
+    #[default]
+    Off,
+    On,
+    /// The state the relay was in when the controller stopped unexpectedly, off after a clean
+    /// shutdown
+    Restore,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+pub enum DeviceKind {
+    Light,
+    Fan,
+    Pump,
+    Heater,
+    #[default]
+    Other,
+}
+
+/// A device on a relay of its own, next to the lights, fan, pump and heater
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DeviceSettings {
+    pub name: String,
+    #[serde(default)]
+    pub kind: DeviceKind,
+    pub relay: u8,
+    /// Replaces the relay's entry in `relay_settings.relay_polarities`
+    #[serde(default)]
+    pub polarity: Option<Polarity>,
+    /// Replaces the relay's entry in `relay_settings.startup_states`
+    #[serde(default)]
+    pub startup_state: Option<StartupState>,
+    /// Only part of this zone instead of every zone
+    #[serde(default)]
+    pub zone: Option<String>,
+    /// The watchdog switches the device off once it has been on for longer than this
+    #[serde(default)]
+    pub max_on_secs: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ThermistorSettings {
+    pub pin: u8,
+    pub voltage_divider_resistance: f32,
+    pub nominal_resistance: f32,
+    pub nominal_temperature: f32,
+    pub thermal_constant: f32,
+    pub resistor: VoltageDividerResistor,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub enum VoltageDividerResistor {
+    R1,
+    R2,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SoilMoistureSettings {
+    pub pin: u8,
+    pub voltage_100: f32,
+    pub voltage_nominal: f32,
+    pub moisture_nominal: f32,
+}
+
+/// Name of the sensor read with `thermistor_settings`
+pub const TEMPERATURE_SENSOR: &str = "temperature";
+/// Name of the sensor read with `soil_moisture_settings`
+pub const SOIL_MOISTURE_SENSOR: &str = "soil_moisture";
+
+/// An extra probe on an ADC channel, read alongside the thermistor and soil moisture sensor
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SensorSettings {
+    pub name: String,
+    pub pin: u8,
+    /// Shown next to the value, e.g. "C" or "%"
+    #[serde(default)]
+    pub unit: String,
+    /// Only read for this zone instead of every zone
+    #[serde(default)]
+    pub zone: Option<String>,
+    #[serde(flatten)]
+    pub kind: SensorKind,
+}
+
+/// How a sensor turns the voltage on its channel into a value
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum SensorKind {
+    /// NTC thermistor in a voltage divider, reads degrees Celsius
+    Thermistor {
+        voltage_divider_resistance: f32,
+        nominal_resistance: f32,
+        nominal_temperature: f32,
+        thermal_constant: f32,
+        resistor: VoltageDividerResistor,
+    },
+    /// Capacitive soil moisture probe, reads the moisture from 0 to 1
+    SoilMoisture {
+        voltage_100: f32,
+        voltage_nominal: f32,
+        moisture_nominal: f32,
+    },
+    /// The voltage itself
+    Voltage,
+    /// `voltage * scale + offset`
+    Linear {
+        scale: f32,
+        #[serde(default)]
+        offset: f32,
+    },
+    /// Interpolates between `[voltage, value]` points sorted by voltage, clamping outside them
+    Curve { points: Vec<[f32; 2]> },
+}
+
+impl ThermistorSettings {
+    pub fn kind(&self) -> SensorKind {
+        SensorKind::Thermistor {
+            voltage_divider_resistance: self.voltage_divider_resistance,
+            nominal_resistance: self.nominal_resistance,
+            nominal_temperature: self.nominal_temperature,
+            thermal_constant: self.thermal_constant,
+            resistor: self.resistor.clone(),
+        }
+    }
+}
+
+impl SoilMoistureSettings {
+    pub fn kind(&self) -> SensorKind {
+        SensorKind::SoilMoisture {
+            voltage_100: self.voltage_100,
+            voltage_nominal: self.voltage_nominal,
+            moisture_nominal: self.moisture_nominal,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WaterPumpSettings {
+    pub grams_per_millisecond: f32,
+    /// The watchdog switches the pump off once it has been running for longer than this
+    #[serde(default = "default_max_run_secs")]
+    pub max_run_secs: u64,
+    /// Pump requests that would exceed this within the last hour are refused
+    #[serde(default = "default_max_grams_per_hour")]
+    pub max_grams_per_hour: u64,
+}
+
+fn default_max_run_secs() -> u64 {
+    20
+}
+
+fn default_max_grams_per_hour() -> u64 {
+    500
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BoardSettings {
+    pub logic_level: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ControllerSettings {
+    pub temperature_set_point_upper: f32,
+    pub temperature_set_point_lower: f32,
+    pub sunlight_hours: u64,
+    pub lights_off_hour: u64,
+    pub watering_frequency_hours: u64,
+    pub watering_amount_grams: u64,
+    #[serde(default)]
+    pub watering_mode: WateringMode,
+    #[serde(default)]
+    pub moisture_watering: MoistureWateringSettings,
+    #[serde(default)]
+    pub watering_schedule: Vec<WateringSlot>,
+    /// Replaces `sunlight_hours` and `lights_off_hour` when set
+    #[serde(default)]
+    pub photoperiod: Option<PhotoperiodSettings>,
+    /// The fan runs above this humidity, read by `climate_settings.humidity_sensor`
+    #[serde(default)]
+    pub humidity_set_point_upper: Option<f32>,
+}
+
+/// Local times at which the lights go on and off, equal times keep them on all day
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PhotoperiodSettings {
+    pub lights_on: NaiveTime,
+    pub lights_off: NaiveTime,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub enum WateringMode {
+    /// Water `watering_amount_grams` every `watering_frequency_hours`
+    #[default]
+    Interval,
+    /// Water `watering_amount_grams` whenever the soil moisture drops below the threshold
+    MoistureThreshold,
+    /// Water at the local times of day listed in `watering_schedule`
+    Schedule,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WateringSlot {
+    pub time: NaiveTime,
+    pub amount_grams: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct MoistureWateringSettings {
+    pub moisture_threshold: f32,
+    pub check_frequency_mins: u64,
+    pub min_interval_hours: u64,
+    pub max_daily_grams: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DataLoggingSettings {
+    pub enabled: bool,
+    pub frequency_mins: u64,
+    pub imaging_frequency_minutes: u64,
+    pub imaging_resolution: ImageResolution,
+    #[serde(default)]
+    pub retention: RetentionSettings,
+}
+
+/// How many days logged data is kept, data without a limit is kept forever
+///
+/// Temperature and soil moisture samples are rolled up into hourly and daily minimums, averages
+/// and maximums before they are removed.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct RetentionSettings {
+    /// Temperature, soil moisture and sensor samples
+    pub raw_days: Option<u64>,
+    pub hourly_days: Option<u64>,
+    pub daily_days: Option<u64>,
+    /// Camera images and when they were taken, without a limit only the latest image file is kept
+    pub image_days: Option<u64>,
+    pub watering_days: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ServerSettings {
+    pub port: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct VentilationSettings {
+    pub frequency_mins: u32,
+    pub duration_mins: u32,
+}
+
+/// The fan cools above `temperature_set_point_upper` until the temperature drops by the
+/// hysteresis, the optional heater heats below `temperature_set_point_lower` until it rises
+/// by the hysteresis. Each relay keeps its state for at least the minimum on/off time.
+///
+/// With a `humidity_sensor`, the fan also runs above `humidity_set_point_upper` until the
+/// humidity drops by `humidity_hysteresis`.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ClimateSettings {
+    pub loop_secs: u64,
+    pub hysteresis: f32,
+    pub fan_min_on_secs: u64,
+    pub fan_min_off_secs: u64,
+    pub heater_min_on_secs: u64,
+    pub heater_min_off_secs: u64,
+    /// Name of a sensor in `sensors` that reads the relative humidity
+    pub humidity_sensor: Option<String>,
+    pub humidity_hysteresis: f32,
+}
+
+/// Grow profiles are sequences of stages, each replacing parts of `controller_settings` while
+/// it is active. Only the profile named `active_profile` is followed.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct GrowSettings {
+    pub active_profile: Option<String>,
+    pub profiles: Vec<GrowProfile>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GrowProfile {
+    pub name: String,
+    pub stages: Vec<GrowStage>,
+}
+
+/// Settings left out keep their value from `controller_settings`
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct GrowStage {
+    pub name: String,
+    /// Stages without a duration last until they are advanced by hand
+    pub duration_days: Option<u64>,
+    pub sunlight_hours: Option<u64>,
+    pub lights_off_hour: Option<u64>,
+    pub photoperiod: Option<PhotoperiodSettings>,
+    pub temperature_set_point_upper: Option<f32>,
+    pub temperature_set_point_lower: Option<f32>,
+    pub watering_frequency_hours: Option<u64>,
+    pub watering_amount_grams: Option<u64>,
+}
+
+/// Dims the lights up after they go on and down before they go off, using a hardware PWM
+/// channel of the Raspberry Pi (0 on GPIO 18, 1 on GPIO 19)
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DimmingSettings {
+    pub enabled: bool,
+    pub pwm_channel: u8,
+    pub frequency_hz: f64,
+    pub ramp_mins: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub enum IoBackend {
+    Hardware,
+    Simulated,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct IoSettings {
+    pub backend: IoBackend,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum StorageBackend {
+    /// CSV files per zone in the data directory
+    Csv,
+    /// A single SQLite database, `growpi.db` in the data directory
+    Sqlite,
+}
+
+/// Where watering records, logged samples and image metadata are kept
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StorageSettings {
+    pub backend: StorageBackend,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SimulationSettings {
+    pub channel_voltages: [f32; 4],
+    pub grow_bucket_model: bool,
+    pub time_acceleration: f32,
+    pub ambient_temperature_day: f32,
+    pub ambient_temperature_night: f32,
+    pub heat_loss_per_hour: f32,
+    pub fan_heat_loss_per_hour: f32,
+    pub light_heating_per_hour: f32,
+    pub heater_heating_per_hour: f32,
+    pub soil_drainage_per_hour: f32,
+    pub soil_moisture_per_gram: f32,
+    pub initial_soil_moisture: f32,
+}
+
+/// Name of the zone formed by the top-level settings when no zones are configured
+pub const DEFAULT_ZONE: &str = "main";
+
+/// A grow bucket with its own relays, sensors and settings, sharing the relay board and ADC
+///
+/// Sections left out are taken from the top-level settings, which only describe a zone of
+/// their own while `zones` is empty.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ZoneSettings {
+    pub name: String,
+    pub light_pin: u8,
+    pub fan_pin: u8,
+    pub water_pump_pin: u8,
+    #[serde(default)]
+    pub heater_pin: Option<u8>,
+    pub thermistor_pin: u8,
+    pub soil_moisture_pin: u8,
+    /// Replaces `grow_settings.active_profile` for this zone
+    #[serde(default)]
+    pub active_profile: Option<String>,
+    #[serde(default)]
+    pub controller_settings: Option<ControllerSettings>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Configuration {
+    pub board_settings: BoardSettings,
+    pub relay_settings: RelaySettings,
+    pub soil_moisture_settings: SoilMoistureSettings,
+    pub thermistor_settings: ThermistorSettings,
+    pub water_pump_settings: WaterPumpSettings,
+    pub controller_settings: ControllerSettings,
+    pub data_logging_settings: DataLoggingSettings,
+    pub server_settings: ServerSettings,
+    pub ventilation_settings: VentilationSettings,
+    #[serde(default)]
+    pub climate_settings: ClimateSettings,
+    #[serde(default)]
+    pub dimming_settings: DimmingSettings,
+    #[serde(default)]
+    pub grow_settings: GrowSettings,
+    #[serde(default)]
+    pub io_settings: IoSettings,
+    #[serde(default)]
+    pub storage_settings: StorageSettings,
+    #[serde(default)]
+    pub simulation_settings: SimulationSettings,
+    #[serde(default)]
+    pub devices: Vec<DeviceSettings>,
+    #[serde(default)]
+    pub sensors: Vec<SensorSettings>,
+    #[serde(default)]
+    pub zones: Vec<ZoneSettings>,
+}
+
+impl Configuration {
+    pub fn from_file(path: &std::path::Path) -> anyhow::Result<Configuration> {
+        let text = std::fs::read_to_string(path)?;
+        let config = toml::from_str(text.as_str())?;
+        Ok(config)
+    }
+
+    /// Loads and validates the configuration, only writing the defaults if there is no file yet
+    pub fn load_or_create(path: &std::path::Path) -> anyhow::Result<Configuration> {
+        if !path.exists() {
+            let config = Configuration::default();
+            config.save_to_file(path).with_context(|| {
+                format!("Could not create default config in {}", path.display())
+            })?;
+            return Ok(config);
+        }
+        let config = Configuration::from_file(path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        config.validate()?;
+        Ok(config)
+    }
+    /// Writes a temporary file and renames it over `path`, so a crash never leaves half a file
+    pub fn save_to_file(&self, path: &std::path::Path) -> anyhow::Result<()> {
+        let text = toml::to_string_pretty(self)?;
+        let temp_path = path.with_extension("toml.tmp");
+        let mut file = std::fs::File::create(&temp_path)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    /// The name and settings of every zone, with the zone settings applied to the top-level ones
+    ///
+    /// Each zone only keeps the devices and sensors that are not reserved for another zone.
+    pub fn resolve_zones(&self) -> Vec<(String, Configuration)> {
+        let zone_devices = |name: &str| {
+            self.devices
+                .iter()
+                .filter(|device| device.zone.as_deref().is_none_or(|zone| zone == name))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let zone_sensors = |name: &str| {
+            self.sensors
+                .iter()
+                .filter(|sensor| sensor.zone.as_deref().is_none_or(|zone| zone == name))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        if self.zones.is_empty() {
+            let mut config = self.clone();
+            config.devices = zone_devices(DEFAULT_ZONE);
+            config.sensors = zone_sensors(DEFAULT_ZONE);
+            return vec![(DEFAULT_ZONE.to_string(), config)];
+        }
+        let mut base = self.clone();
+        base.zones = Vec::new();
+        self.zones
+            .iter()
+            .map(|zone| {
+                let mut config = base.clone();
+                config.devices = zone_devices(&zone.name);
+                config.sensors = zone_sensors(&zone.name);
+                config.relay_settings.light_pin = zone.light_pin;
+                config.relay_settings.fan_pin = zone.fan_pin;
+                config.relay_settings.water_pump_pin = zone.water_pump_pin;
+                config.relay_settings.heater_pin = zone.heater_pin;
+                config.thermistor_settings.pin = zone.thermistor_pin;
+                config.soil_moisture_settings.pin = zone.soil_moisture_pin;
+                if let Some(active_profile) = &zone.active_profile {
+                    config.grow_settings.active_profile = Some(active_profile.clone());
+                }
+                if let Some(controller_settings) = &zone.controller_settings {
+                    config.controller_settings = controller_settings.clone();
+                }
+                (zone.name.clone(), config)
+            })
+            .collect()
+    }
+
+    /// The polarity of every relay in `relay_gpio_pins`
+    pub fn relay_polarities(&self) -> Vec<Polarity> {
+        self.per_relay(&self.relay_settings.relay_polarities, |device| {
+            device.polarity
+        })
+    }
+
+    /// The startup state of every relay in `relay_gpio_pins`
+    pub fn relay_startup_states(&self) -> Vec<StartupState> {
+        self.per_relay(&self.relay_settings.startup_states, |device| {
+            device.startup_state
+        })
+    }
+
+    /// One value per relay, taken from the device on the relay or the list in `relay_settings`
+    fn per_relay<T: Copy + Default>(
+        &self,
+        values: &[T],
+        device_value: impl Fn(&DeviceSettings) -> Option<T>,
+    ) -> Vec<T> {
+        let mut values = (0..self.relay_settings.relay_gpio_pins.len())
+            .map(|relay| values.get(relay).copied().unwrap_or_default())
+            .collect::<Vec<_>>();
+        for device in &self.devices {
+            if let (Some(value), Some(device_value)) =
+                (values.get_mut(device.relay as usize), device_value(device))
+            {
+                *value = device_value;
+            }
+        }
+        values
+    }
+
+    /// The settings section called `name`, as it appears in growpi.toml
+    pub fn get_section(&self, name: &str) -> anyhow::Result<toml::Value> {
+        let toml::Value::Table(mut sections) = toml::Value::try_from(self)? else {
+            bail!("Configuration is not a table");
+        };
+        sections
+            .remove(name)
+            .with_context(|| format!("No settings section {}", name))
+    }
+
+    /// A copy of this configuration with the section called `name` replaced by `section`
+    pub fn with_section(&self, name: &str, section: toml::Value) -> anyhow::Result<Configuration> {
+        let toml::Value::Table(mut sections) = toml::Value::try_from(self)? else {
+            bail!("Configuration is not a table");
+        };
+        if !sections.contains_key(name) {
+            bail!("No settings section {}", name);
+        }
+        sections.insert(name.to_string(), section);
+        Ok(toml::Value::Table(sections).try_into()?)
+    }
+
+    /// Names of the settings sections that differ between this configuration and `other`
+    pub fn changed_sections(&self, other: &Configuration) -> anyhow::Result<Vec<String>> {
+        let (toml::Value::Table(sections), toml::Value::Table(other_sections)) =
+            (toml::Value::try_from(self)?, toml::Value::try_from(other)?)
+        else {
+            bail!("Configuration is not a table");
+        };
+        let mut names = sections
+            .keys()
+            .chain(other_sections.keys())
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        Ok(names
+            .into_iter()
+            .filter(|name| sections.get(*name) != other_sections.get(*name))
+            .cloned()
+            .collect())
+    }
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Self {
+            board_settings: BoardSettings { logic_level: 3.3 },
+            relay_settings: RelaySettings {
+                light_pin: 0,
+                fan_pin: 1,
+                water_pump_pin: 2,
+                heater_pin: None,
+                relay_gpio_pins: [17, 27, 22, -1].to_vec(),
+                relay_polarities: Vec::new(),
+                startup_states: Vec::new(),
+            },
+            soil_moisture_settings: SoilMoistureSettings {
+                pin: 1,
+                voltage_100: 1.417,
+                voltage_nominal: 2.823,
+                moisture_nominal: 0.41,
+            },
+            thermistor_settings: ThermistorSettings {
+                pin: 0,
+                voltage_divider_resistance: 9_700.,
+                nominal_resistance: 10_000.,
+                nominal_temperature: 298.15,
+                thermal_constant: 3950.,
+                resistor: VoltageDividerResistor::R2,
+            },
+            water_pump_settings: WaterPumpSettings {
+                grams_per_millisecond: 0.05281,
+                max_run_secs: default_max_run_secs(),
+                max_grams_per_hour: default_max_grams_per_hour(),
+            },
+            controller_settings: ControllerSettings {
+                temperature_set_point_upper: 35.,
+                temperature_set_point_lower: 28.,
+                sunlight_hours: 24,
+                watering_frequency_hours: 30,
+                watering_amount_grams: 200,
+                lights_off_hour: 0,
+                watering_mode: WateringMode::Interval,
+                moisture_watering: MoistureWateringSettings::default(),
+                watering_schedule: [
+                    WateringSlot {
+                        time: NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+                        amount_grams: 100,
+                    },
+                    WateringSlot {
+                        time: NaiveTime::from_hms_opt(19, 0, 0).unwrap(),
+                        amount_grams: 100,
+                    },
+                ]
+                .to_vec(),
+                photoperiod: None,
+                humidity_set_point_upper: None,
+            },
+            data_logging_settings: DataLoggingSettings {
+                enabled: true,
+                frequency_mins: 60,
+                imaging_frequency_minutes: 60,
+                imaging_resolution: ImageResolution::R480p,
+                retention: RetentionSettings::default(),
+            },
+            server_settings: ServerSettings { port: 2205 },
+            ventilation_settings: VentilationSettings::default(),
+            climate_settings: ClimateSettings::default(),
+            dimming_settings: DimmingSettings::default(),
+            grow_settings: GrowSettings {
+                active_profile: None,
+                profiles: [GrowProfile {
+                    name: "Example".to_string(),
+                    stages: [
+                        GrowStage {
+                            name: "Seedling".to_string(),
+                            duration_days: Some(14),
+                            sunlight_hours: Some(18),
+                            watering_amount_grams: Some(100),
+                            ..Default::default()
+                        },
+                        GrowStage {
+                            name: "Vegetative".to_string(),
+                            duration_days: Some(35),
+                            sunlight_hours: Some(18),
+                            ..Default::default()
+                        },
+                        GrowStage {
+                            name: "Flowering".to_string(),
+                            sunlight_hours: Some(12),
+                            watering_amount_grams: Some(300),
+                            ..Default::default()
+                        },
+                    ]
+                    .to_vec(),
+                }]
+                .to_vec(),
+            },
+            io_settings: IoSettings::default(),
+            storage_settings: StorageSettings::default(),
+            simulation_settings: SimulationSettings::default(),
+            devices: Vec::new(),
+            sensors: Vec::new(),
+            zones: Vec::new(),
+        }
+    }
+}
+
+impl Default for VentilationSettings {
+    fn default() -> VentilationSettings {
+        VentilationSettings {
+            frequency_mins: 30,
+            duration_mins: 3,
+        }
+    }
+}
+
+impl Default for ClimateSettings {
+    fn default() -> ClimateSettings {
+        ClimateSettings {
+            loop_secs: 30,
+            hysteresis: 1.,
+            fan_min_on_secs: 60,
+            fan_min_off_secs: 60,
+            heater_min_on_secs: 120,
+            heater_min_off_secs: 120,
+            humidity_sensor: None,
+            humidity_hysteresis: 5.,
+        }
+    }
+}
+
+impl Default for DimmingSettings {
+    fn default() -> DimmingSettings {
+        DimmingSettings {
+            enabled: false,
+            pwm_channel: 0,
+            frequency_hz: 1000.,
+            ramp_mins: 30,
+        }
+    }
+}
+
+impl Default for MoistureWateringSettings {
+    fn default() -> MoistureWateringSettings {
+        MoistureWateringSettings {
+            moisture_threshold: 0.3,
+            check_frequency_mins: 30,
+            min_interval_hours: 12,
+            max_daily_grams: 600,
+        }
+    }
+}
+
+impl Default for IoSettings {
+    fn default() -> IoSettings {
+        IoSettings {
+            backend: IoBackend::Hardware,
+        }
+    }
+}
+
+impl Default for StorageSettings {
+    fn default() -> StorageSettings {
+        StorageSettings {
+            backend: StorageBackend::Csv,
+        }
+    }
+}
+
+impl Default for SimulationSettings {
+    fn default() -> SimulationSettings {
+        SimulationSettings {
+            // Thermistor at 25C on A0, soil probe at its nominal moisture on A1
+            channel_voltages: [1.675, 2.823, 0., 0.],
+            grow_bucket_model: false,
+            time_acceleration: 1.,
+            ambient_temperature_day: 24.,
+            ambient_temperature_night: 18.,
+            heat_loss_per_hour: 0.5,
+            fan_heat_loss_per_hour: 3.,
+            light_heating_per_hour: 6.,
+            heater_heating_per_hour: 4.,
+            soil_drainage_per_hour: 0.01,
+            soil_moisture_per_gram: 0.001,
+            initial_soil_moisture: 0.41,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_default() {
+        let config = Configuration::default();
+        config
+            .save_to_file(std::path::Path::new("./growpi.toml"))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_sections() {
+        let config = Configuration::default();
+        assert!(config.validate().is_ok());
+
+        let mut section = config.get_section("controller_settings").unwrap();
+        section["sunlight_hours"] = toml::Value::Integer(12);
+        let changed = config.with_section("controller_settings", section).unwrap();
+        assert_eq!(changed.controller_settings.sunlight_hours, 12);
+        assert_eq!(
+            config.changed_sections(&changed).unwrap(),
+            ["controller_settings"]
+        );
+
+        assert!(config.get_section("nonsense").is_err());
+        let section = toml::Value::String("nonsense".to_string());
+        assert!(config.with_section("server_settings", section).is_err());
+
+        let mut invalid = config.clone();
+        invalid.controller_settings.temperature_set_point_lower = 40.;
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_zones() {
+        let mut config = Configuration::default();
+        let zones = config.resolve_zones();
+        assert_eq!(zones.len(), 1);
+        assert_eq!(zones[0].0, DEFAULT_ZONE);
+
+        config.relay_settings.relay_gpio_pins = vec![17, 27, 22, 23, 24, 25];
+        let zone = |name: &str, first_relay, thermistor_pin| ZoneSettings {
+            name: name.to_string(),
+            light_pin: first_relay,
+            fan_pin: first_relay + 1,
+            water_pump_pin: first_relay + 2,
+            heater_pin: None,
+            thermistor_pin,
+            soil_moisture_pin: thermistor_pin + 1,
+            active_profile: None,
+            controller_settings: None,
+        };
+        config.zones = vec![zone("left", 0, 0), zone("right", 3, 2)];
+        config.zones[1].controller_settings = Some(ControllerSettings {
+            sunlight_hours: 12,
+            ..config.controller_settings.clone()
+        });
+        assert!(config.validate().is_ok());
+        let zones = config.resolve_zones();
+        let (name, right) = &zones[1];
+        assert_eq!(name, "right");
+        assert_eq!(right.relay_settings.water_pump_pin, 5);
+        assert_eq!(right.soil_moisture_settings.pin, 3);
+        assert_eq!(right.controller_settings.sunlight_hours, 12);
+        assert_eq!(zones[0].1.controller_settings.sunlight_hours, 24);
+
+        // Zones share the relay board, so they can't share relays
+        config.zones[1].light_pin = 2;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_sensors() {
+        let mut config = Configuration::default();
+        let text = r#"
+            [[sensors]]
+            name = "light"
+            pin = 2
+            unit = "%"
+            type = "Linear"
+            scale = 30
+
+            [[sensors]]
+            name = "water_level"
+            pin = 3
+            zone = "main"
+            type = "Curve"
+            points = [[0.5, 0], [2.5, 100]]
+        "#;
+        #[derive(Deserialize)]
+        struct Sensors {
+            sensors: Vec<SensorSettings>,
+        }
+        config.sensors = toml::from_str::<Sensors>(text).unwrap().sensors;
+        assert!(config.validate().is_ok());
+        assert!(matches!(
+            config.sensors[0].kind,
+            SensorKind::Linear { scale, offset } if scale == 30. && offset == 0.
+        ));
+
+        let saved = toml::to_string_pretty(&config).unwrap();
+        let loaded: Configuration = toml::from_str(&saved).unwrap();
+        assert_eq!(loaded.sensors.len(), 2);
+        assert_eq!(loaded.sensors[1].zone.as_deref(), Some(DEFAULT_ZONE));
+
+        config.sensors[1].zone = Some("left".to_string());
+        assert!(config.validate().is_err());
+    }
+}
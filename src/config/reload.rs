@@ -0,0 +1,73 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::Context;
+use notify::{RecursiveMode, Watcher};
+
+use super::Configuration;
+use crate::state::{self, ProgramStateShared};
+
+/// Editors save in several steps, so changes are applied once the file has been quiet this long
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// Applies changes to the configuration file while running, logging what was applied or rejected
+pub async fn watch_config_file(program_state: ProgramStateShared) {
+    if let Err(e) = watch(&program_state).await {
+        eprintln!("Not watching the configuration file: {:#}", e);
+    }
+}
+
+async fn watch(program_state: &ProgramStateShared) -> anyhow::Result<()> {
+    let config_path = program_state.lock().await.paths.config.clone();
+    let file_name = config_path
+        .file_name()
+        .context("Configuration path has no file name")?
+        .to_owned();
+    // The file is replaced on save, so the directory is watched rather than the file itself
+    let directory = match config_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            let _ = sender.send(event);
+        }
+    })?;
+    watcher.watch(directory, RecursiveMode::NonRecursive)?;
+
+    while let Some(event) = receiver.recv().await {
+        let is_config_file = |event: &notify::Event| {
+            event
+                .paths
+                .iter()
+                .any(|path| path.file_name() == Some(file_name.as_os_str()))
+        };
+        if !is_config_file(&event) || event.kind.is_access() {
+            continue;
+        }
+        while let Ok(Some(_)) = tokio::time::timeout(SETTLE_TIME, receiver.recv()).await {}
+        reload(program_state, &config_path).await;
+    }
+    Ok(())
+}
+
+async fn reload(program_state: &ProgramStateShared, config_path: &Path) {
+    let config = match Configuration::from_file(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Configuration change rejected, could not read it: {:#}", e);
+            return;
+        }
+    };
+    let mut program_state = program_state.lock().await;
+    match state::apply_config(&mut program_state, config) {
+        // Saves made through the API were applied already
+        Ok(changed_sections) if changed_sections.is_empty() => (),
+        Ok(changed_sections) => eprintln!(
+            "Configuration reloaded, applied {}",
+            changed_sections.join(", ")
+        ),
+        Err(e) => eprintln!("Configuration change rejected: {:#}", e),
+    }
+}
@@ -0,0 +1,756 @@
+use std::fmt::Display;
+
+use super::{
+    Configuration, ControllerSettings, GrowStage, SensorKind, StartupState, StorageBackend,
+    BUILT_IN_DEVICES, DEFAULT_ZONE, SOIL_MOISTURE_SENSOR, TEMPERATURE_SENSOR,
+};
+
+/// Highest BCM GPIO number on the Raspberry Pi header
+const MAX_GPIO_PIN: i16 = 27;
+
+/// Most grams the pump can be asked for in one watering
+const MAX_WATERING_GRAMS: u64 = u16::MAX as u64;
+
+/// A problem with a configuration value, located by its TOML path
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigProblem {
+    pub path: String,
+    pub message: String,
+}
+
+impl Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Every problem found in a configuration
+#[derive(Debug)]
+pub struct ValidationError {
+    pub problems: Vec<ConfigProblem>,
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for problem in &self.problems {
+            write!(f, "\n  {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Zone and sensor names end up in file names and URLs
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[derive(Default)]
+struct Problems(Vec<ConfigProblem>);
+
+impl Problems {
+    fn check(&mut self, valid: bool, path: impl Into<String>, message: impl Into<String>) {
+        if !valid {
+            self.0.push(ConfigProblem {
+                path: path.into(),
+                message: message.into(),
+            });
+        }
+    }
+}
+
+impl Configuration {
+    /// Checks the values that parse fine but cannot work, reporting all problems at once
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let mut problems = Problems::default();
+        self.validate_relays(&mut problems);
+        self.validate_sensors(&mut problems);
+        self.validate_controller(&mut problems);
+        self.validate_grow_profiles(&mut problems);
+        self.validate_devices(&mut problems);
+        self.validate_zones(&mut problems);
+        self.validate_misc(&mut problems);
+        match problems.0.is_empty() {
+            true => Ok(()),
+            false => Err(ValidationError {
+                problems: problems.0,
+            }),
+        }
+    }
+
+    fn validate_relays(&self, problems: &mut Problems) {
+        let relay_settings = &self.relay_settings;
+        let gpio_pins = &relay_settings.relay_gpio_pins;
+        for (index, gpio_pin) in gpio_pins.iter().enumerate() {
+            problems.check(
+                *gpio_pin == -1 || (0..=MAX_GPIO_PIN).contains(gpio_pin),
+                format!("relay_settings.relay_gpio_pins[{}]", index),
+                format!(
+                    "GPIO {} doesn't exist, use 0-{} or -1 for no relay",
+                    gpio_pin, MAX_GPIO_PIN
+                ),
+            );
+        }
+
+        for (name, len) in [
+            ("relay_polarities", relay_settings.relay_polarities.len()),
+            ("startup_states", relay_settings.startup_states.len()),
+        ] {
+            problems.check(
+                len <= gpio_pins.len(),
+                format!("relay_settings.{}", name),
+                format!(
+                    "has {} entries, but relay_gpio_pins only has {}",
+                    len,
+                    gpio_pins.len()
+                ),
+            );
+        }
+        // A pump that starts on would water without any of the watering limits
+        let pump_pins = match self.zones.is_empty() {
+            true => vec![relay_settings.water_pump_pin],
+            false => self.zones.iter().map(|zone| zone.water_pump_pin).collect(),
+        };
+        let startup_states = self.relay_startup_states();
+        for pin in pump_pins {
+            problems.check(
+                startup_states
+                    .get(pin as usize)
+                    .is_none_or(|state| *state == StartupState::Off),
+                format!("relay_settings.startup_states[{}]", pin),
+                format!("relay {} runs a water pump and has to start off", pin),
+            );
+        }
+
+        // With zones configured, the top-level relays belong to no zone and are left unused
+        let relays = match self.zones.is_empty() {
+            true => vec![
+                (
+                    "relay_settings.light_pin".to_string(),
+                    Some(relay_settings.light_pin),
+                ),
+                (
+                    "relay_settings.fan_pin".to_string(),
+                    Some(relay_settings.fan_pin),
+                ),
+                (
+                    "relay_settings.water_pump_pin".to_string(),
+                    Some(relay_settings.water_pump_pin),
+                ),
+                (
+                    "relay_settings.heater_pin".to_string(),
+                    relay_settings.heater_pin,
+                ),
+            ],
+            false => self
+                .zones
+                .iter()
+                .enumerate()
+                .flat_map(|(index, zone)| {
+                    [
+                        ("light_pin", Some(zone.light_pin)),
+                        ("fan_pin", Some(zone.fan_pin)),
+                        ("water_pump_pin", Some(zone.water_pump_pin)),
+                        ("heater_pin", zone.heater_pin),
+                    ]
+                    .map(|(name, pin)| (format!("zones[{}].{}", index, name), pin))
+                })
+                .collect(),
+        };
+        let devices = self
+            .devices
+            .iter()
+            .enumerate()
+            .map(|(index, device)| (format!("devices[{}].relay", index), Some(device.relay)));
+        let relays = relays
+            .into_iter()
+            .chain(devices)
+            .filter_map(|(path, pin)| pin.map(|pin| (path, pin)))
+            .collect::<Vec<_>>();
+        for (index, (path, pin)) in relays.iter().enumerate() {
+            match gpio_pins.get(*pin as usize) {
+                None => problems.check(
+                    false,
+                    path,
+                    format!(
+                        "relay {} is out of range of relay_gpio_pins, which has {} entries",
+                        pin,
+                        gpio_pins.len()
+                    ),
+                ),
+                Some(gpio_pin) => problems.check(
+                    *gpio_pin != -1,
+                    path,
+                    format!("relay {} has no GPIO pin in relay_gpio_pins", pin),
+                ),
+            }
+            if let Some((other, _)) = relays[..index].iter().find(|(_, other)| other == pin) {
+                problems.check(
+                    false,
+                    path,
+                    format!("relay {} is already used by {}", pin, other),
+                );
+            }
+        }
+    }
+
+    fn validate_sensors(&self, problems: &mut Problems) {
+        let mut sensors = vec![
+            (
+                "thermistor_settings.pin".to_string(),
+                self.thermistor_settings.pin,
+            ),
+            (
+                "soil_moisture_settings.pin".to_string(),
+                self.soil_moisture_settings.pin,
+            ),
+        ];
+        for (index, zone) in self.zones.iter().enumerate() {
+            sensors.push((
+                format!("zones[{}].thermistor_pin", index),
+                zone.thermistor_pin,
+            ));
+            sensors.push((
+                format!("zones[{}].soil_moisture_pin", index),
+                zone.soil_moisture_pin,
+            ));
+        }
+        for (index, sensor) in self.sensors.iter().enumerate() {
+            sensors.push((format!("sensors[{}].pin", index), sensor.pin));
+        }
+        for (path, pin) in sensors {
+            problems.check(
+                pin <= 3,
+                path,
+                format!("ADC pin {} doesn't exist, use 0-3", pin),
+            );
+        }
+        problems.check(
+            self.board_settings.logic_level > 0.,
+            "board_settings.logic_level",
+            "must be above 0",
+        );
+        problems.check(
+            self.water_pump_settings.grams_per_millisecond > 0.,
+            "water_pump_settings.grams_per_millisecond",
+            "must be above 0, or the pump never stops",
+        );
+        self.validate_sensor_registry(problems);
+    }
+
+    fn validate_sensor_registry(&self, problems: &mut Problems) {
+        for (index, sensor) in self.sensors.iter().enumerate() {
+            let path = format!("sensors[{}]", index);
+            problems.check(
+                is_valid_name(&sensor.name),
+                format!("{}.name", path),
+                format!(
+                    "\"{}\" is not a valid sensor name, use letters, digits, - and _",
+                    sensor.name
+                ),
+            );
+            let taken = [TEMPERATURE_SENSOR, SOIL_MOISTURE_SENSOR].contains(&sensor.name.as_str())
+                || self.sensors[..index]
+                    .iter()
+                    .any(|other| other.name == sensor.name);
+            problems.check(
+                !taken,
+                format!("{}.name", path),
+                format!("there is already a sensor called {}", sensor.name),
+            );
+            if let Some(zone) = &sensor.zone {
+                problems.check(
+                    self.zone_exists(zone),
+                    format!("{}.zone", path),
+                    format!("there is no zone called {}", zone),
+                );
+            }
+            if let SensorKind::Curve { points } = &sensor.kind {
+                problems.check(
+                    points.len() >= 2,
+                    format!("{}.points", path),
+                    "a curve needs at least two points",
+                );
+                problems.check(
+                    points.windows(2).all(|pair| pair[0][0] < pair[1][0]),
+                    format!("{}.points", path),
+                    "points must be sorted by voltage, without repeating a voltage",
+                );
+            }
+        }
+    }
+
+    fn validate_devices(&self, problems: &mut Problems) {
+        for (index, device) in self.devices.iter().enumerate() {
+            let path = format!("devices[{}]", index);
+            problems.check(
+                is_valid_name(&device.name),
+                format!("{}.name", path),
+                format!(
+                    "\"{}\" is not a valid device name, use letters, digits, - and _",
+                    device.name
+                ),
+            );
+            let taken = BUILT_IN_DEVICES.contains(&device.name.as_str())
+                || self.devices[..index]
+                    .iter()
+                    .any(|other| other.name == device.name);
+            problems.check(
+                !taken,
+                format!("{}.name", path),
+                format!("there is already a device called {}", device.name),
+            );
+            if let Some(zone) = &device.zone {
+                problems.check(
+                    self.zone_exists(zone),
+                    format!("{}.zone", path),
+                    format!("there is no zone called {}", zone),
+                );
+            }
+            problems.check(
+                device.max_on_secs != Some(0),
+                format!("{}.max_on_secs", path),
+                "must be above 0, leave it out for no limit",
+            );
+        }
+    }
+
+    fn zone_exists(&self, zone: &str) -> bool {
+        match self.zones.is_empty() {
+            true => zone == DEFAULT_ZONE,
+            false => self.zones.iter().any(|other| other.name == zone),
+        }
+    }
+
+    fn validate_controller(&self, problems: &mut Problems) {
+        validate_controller_settings(&self.controller_settings, "controller_settings", problems);
+    }
+
+    fn validate_zones(&self, problems: &mut Problems) {
+        for (index, zone) in self.zones.iter().enumerate() {
+            let path = format!("zones[{}]", index);
+            problems.check(
+                is_valid_name(&zone.name),
+                format!("{}.name", path),
+                format!(
+                    "\"{}\" is not a valid zone name, use letters, digits, - and _",
+                    zone.name
+                ),
+            );
+            let used_before = self.zones[..index]
+                .iter()
+                .any(|other| other.name == zone.name);
+            problems.check(
+                !used_before,
+                format!("{}.name", path),
+                format!("there is already a zone called {}", zone.name),
+            );
+            if let Some(active_profile) = &zone.active_profile {
+                problems.check(
+                    self.grow_settings
+                        .profiles
+                        .iter()
+                        .any(|profile| &profile.name == active_profile),
+                    format!("{}.active_profile", path),
+                    format!("there is no profile called {}", active_profile),
+                );
+            }
+            if let Some(settings) = &zone.controller_settings {
+                let path = format!("{}.controller_settings", path);
+                validate_controller_settings(settings, &path, problems);
+            }
+        }
+    }
+
+    fn validate_grow_profiles(&self, problems: &mut Problems) {
+        let grow_settings = &self.grow_settings;
+        if let Some(active_profile) = &grow_settings.active_profile {
+            problems.check(
+                grow_settings
+                    .profiles
+                    .iter()
+                    .any(|profile| &profile.name == active_profile),
+                "grow_settings.active_profile",
+                format!("there is no profile called {}", active_profile),
+            );
+        }
+        for (profile_index, profile) in grow_settings.profiles.iter().enumerate() {
+            let path = format!("grow_settings.profiles[{}]", profile_index);
+            problems.check(
+                !profile.stages.is_empty(),
+                format!("{}.stages", path),
+                "a profile needs at least one stage",
+            );
+            for (stage_index, stage) in profile.stages.iter().enumerate() {
+                let path = format!("{}.stages[{}]", path, stage_index);
+                validate_stage(stage, &path, problems);
+            }
+        }
+    }
+
+    fn validate_misc(&self, problems: &mut Problems) {
+        problems.check(
+            self.climate_settings.loop_secs > 0,
+            "climate_settings.loop_secs",
+            "must be above 0",
+        );
+        problems.check(
+            self.climate_settings.hysteresis >= 0.,
+            "climate_settings.hysteresis",
+            "must not be negative",
+        );
+        if let Some(humidity_sensor) = &self.climate_settings.humidity_sensor {
+            problems.check(
+                self.sensors
+                    .iter()
+                    .any(|sensor| &sensor.name == humidity_sensor),
+                "climate_settings.humidity_sensor",
+                format!("there is no sensor called {}", humidity_sensor),
+            );
+        }
+        problems.check(
+            self.climate_settings.humidity_hysteresis >= 0.,
+            "climate_settings.humidity_hysteresis",
+            "must not be negative",
+        );
+        if self.dimming_settings.enabled {
+            problems.check(
+                self.dimming_settings.pwm_channel <= 1,
+                "dimming_settings.pwm_channel",
+                format!(
+                    "PWM channel {} doesn't exist, use 0-1",
+                    self.dimming_settings.pwm_channel
+                ),
+            );
+        }
+        problems.check(
+            self.data_logging_settings.frequency_mins > 0,
+            "data_logging_settings.frequency_mins",
+            "must be above 0",
+        );
+        // A frequency of 0 turns the ventilation off
+        let ventilation = &self.ventilation_settings;
+        problems.check(
+            ventilation.frequency_mins == 0
+                || ventilation.duration_mins < ventilation.frequency_mins,
+            "ventilation_settings.duration_mins",
+            format!(
+                "{} is not shorter than frequency_mins ({}), the fan would never stop",
+                ventilation.duration_mins, ventilation.frequency_mins
+            ),
+        );
+        problems.check(
+            self.simulation_settings.time_acceleration > 0.,
+            "simulation_settings.time_acceleration",
+            "must be above 0",
+        );
+        let retention = &self.data_logging_settings.retention;
+        for (name, days) in [
+            ("raw_days", retention.raw_days),
+            ("hourly_days", retention.hourly_days),
+            ("daily_days", retention.daily_days),
+            ("image_days", retention.image_days),
+            ("watering_days", retention.watering_days),
+        ] {
+            // Samples are rolled up once their day is over, so they have to be kept that long
+            problems.check(
+                days.is_none_or(|days| days > 0),
+                format!("data_logging_settings.retention.{}", name),
+                "must be at least 1 day",
+            );
+        }
+        if let Some(days) = retention.watering_days {
+            let controller_settings =
+                std::iter::once(("controller_settings".to_string(), &self.controller_settings))
+                    .chain(self.zones.iter().enumerate().filter_map(|(index, zone)| {
+                        let path = format!("zones[{}].controller_settings", index);
+                        zone.controller_settings
+                            .as_ref()
+                            .map(|settings| (path, settings))
+                    }));
+            // The watering intervals are checked against the history that is kept
+            for (path, settings) in controller_settings {
+                for (name, hours) in [
+                    (
+                        "watering_frequency_hours",
+                        settings.watering_frequency_hours,
+                    ),
+                    (
+                        "moisture_watering.min_interval_hours",
+                        settings.moisture_watering.min_interval_hours,
+                    ),
+                ] {
+                    problems.check(
+                        days.saturating_mul(24) >= hours,
+                        "data_logging_settings.retention.watering_days",
+                        format!(
+                            "{} days is shorter than {}.{} ({} hours)",
+                            days, path, name, hours
+                        ),
+                    );
+                }
+            }
+        }
+        problems.check(
+            cfg!(feature = "sqlite") || self.storage_settings.backend != StorageBackend::Sqlite,
+            "storage_settings.backend",
+            "growpi was built without the sqlite feature",
+        );
+    }
+}
+
+fn validate_controller_settings(
+    settings: &ControllerSettings,
+    path: &str,
+    problems: &mut Problems,
+) {
+    problems.check(
+        settings.temperature_set_point_lower <= settings.temperature_set_point_upper,
+        format!("{}.temperature_set_point_lower", path),
+        format!(
+            "{} is above temperature_set_point_upper ({})",
+            settings.temperature_set_point_lower, settings.temperature_set_point_upper
+        ),
+    );
+    problems.check(
+        settings.sunlight_hours <= 24,
+        format!("{}.sunlight_hours", path),
+        format!(
+            "{} is more than the 24 hours of a day",
+            settings.sunlight_hours
+        ),
+    );
+    problems.check(
+        settings.lights_off_hour <= 23,
+        format!("{}.lights_off_hour", path),
+        format!(
+            "{} is not an hour of the day, use 0-23",
+            settings.lights_off_hour
+        ),
+    );
+    problems.check(
+        settings.watering_frequency_hours > 0,
+        format!("{}.watering_frequency_hours", path),
+        "must be above 0",
+    );
+    problems.check(
+        settings.watering_amount_grams <= MAX_WATERING_GRAMS,
+        format!("{}.watering_amount_grams", path),
+        format!(
+            "{} is more than the {}g that can be watered at once",
+            settings.watering_amount_grams, MAX_WATERING_GRAMS
+        ),
+    );
+    for (index, slot) in settings.watering_schedule.iter().enumerate() {
+        problems.check(
+            slot.amount_grams <= MAX_WATERING_GRAMS,
+            format!("{}.watering_schedule[{}].amount_grams", path, index),
+            format!(
+                "{} is more than the {}g that can be watered at once",
+                slot.amount_grams, MAX_WATERING_GRAMS
+            ),
+        );
+    }
+    let threshold = settings.moisture_watering.moisture_threshold;
+    problems.check(
+        (0. ..=1.).contains(&threshold),
+        format!("{}.moisture_watering.moisture_threshold", path),
+        format!("{} is not a soil moisture, use 0-1", threshold),
+    );
+    problems.check(
+        settings.moisture_watering.check_frequency_mins > 0,
+        format!("{}.moisture_watering.check_frequency_mins", path),
+        "must be above 0",
+    );
+}
+
+fn validate_stage(stage: &GrowStage, path: &str, problems: &mut Problems) {
+    if let Some(sunlight_hours) = stage.sunlight_hours {
+        problems.check(
+            sunlight_hours <= 24,
+            format!("{}.sunlight_hours", path),
+            format!("{} is more than the 24 hours of a day", sunlight_hours),
+        );
+    }
+    if let Some(lights_off_hour) = stage.lights_off_hour {
+        problems.check(
+            lights_off_hour <= 23,
+            format!("{}.lights_off_hour", path),
+            format!("{} is not an hour of the day, use 0-23", lights_off_hour),
+        );
+    }
+    problems.check(
+        stage.watering_frequency_hours != Some(0),
+        format!("{}.watering_frequency_hours", path),
+        "must be above 0",
+    );
+    if let Some(amount) = stage.watering_amount_grams {
+        problems.check(
+            amount <= MAX_WATERING_GRAMS,
+            format!("{}.watering_amount_grams", path),
+            format!(
+                "{} is more than the {}g that can be watered at once",
+                amount, MAX_WATERING_GRAMS
+            ),
+        );
+    }
+    if let (Some(lower), Some(upper)) = (
+        stage.temperature_set_point_lower,
+        stage.temperature_set_point_upper,
+    ) {
+        problems.check(
+            lower <= upper,
+            format!("{}.temperature_set_point_lower", path),
+            format!("{} is above temperature_set_point_upper ({})", lower, upper),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DeviceKind, DeviceSettings, Polarity, SensorSettings};
+
+    #[test]
+    fn test_validate() {
+        assert!(Configuration::default().validate().is_ok());
+
+        let mut config = Configuration::default();
+        config.relay_settings.relay_gpio_pins = vec![17, 27, -1];
+        config.relay_settings.fan_pin = 0;
+        config.relay_settings.water_pump_pin = 2;
+        config.relay_settings.heater_pin = Some(5);
+        config.thermistor_settings.pin = 4;
+        config.controller_settings.temperature_set_point_lower = 40.;
+        config.controller_settings.sunlight_hours = 25;
+        config.water_pump_settings.grams_per_millisecond = 0.;
+        config.grow_settings.profiles[0].stages[1].sunlight_hours = Some(30);
+        config.relay_settings.startup_states = vec![StartupState::On; 4];
+        config.devices = vec![DeviceSettings {
+            name: "fan".to_string(),
+            kind: DeviceKind::Fan,
+            relay: 0,
+            polarity: Some(Polarity::ActiveHigh),
+            startup_state: None,
+            zone: None,
+            max_on_secs: None,
+        }];
+        config.sensors = vec![SensorSettings {
+            name: "temperature".to_string(),
+            pin: 2,
+            unit: String::new(),
+            zone: Some("left".to_string()),
+            kind: SensorKind::Curve {
+                points: vec![[1., 0.], [0.5, 1.]],
+            },
+        }];
+        config.data_logging_settings.retention.raw_days = Some(0);
+        config.climate_settings.humidity_sensor = Some("humidity".to_string());
+
+        let paths = config
+            .validate()
+            .unwrap_err()
+            .problems
+            .into_iter()
+            .map(|problem| problem.path)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "relay_settings.startup_states",
+                "relay_settings.startup_states[2]",
+                "relay_settings.fan_pin",
+                "relay_settings.water_pump_pin",
+                "relay_settings.heater_pin",
+                "devices[0].relay",
+                "thermistor_settings.pin",
+                "water_pump_settings.grams_per_millisecond",
+                "sensors[0].name",
+                "sensors[0].zone",
+                "sensors[0].points",
+                "controller_settings.temperature_set_point_lower",
+                "controller_settings.sunlight_hours",
+                "grow_settings.profiles[0].stages[1].sunlight_hours",
+                "devices[0].name",
+                "climate_settings.humidity_sensor",
+                "data_logging_settings.retention.raw_days",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_intervals() {
+        let mut config = Configuration::default();
+        config.controller_settings.watering_frequency_hours = 0;
+        config
+            .controller_settings
+            .moisture_watering
+            .check_frequency_mins = 0;
+        config.grow_settings.profiles[0].stages[0].watering_frequency_hours = Some(0);
+        config.data_logging_settings.frequency_mins = 0;
+        config.ventilation_settings.frequency_mins = 10;
+        config.ventilation_settings.duration_mins = 10;
+
+        let paths = config
+            .validate()
+            .unwrap_err()
+            .problems
+            .into_iter()
+            .map(|problem| problem.path)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "controller_settings.watering_frequency_hours",
+                "controller_settings.moisture_watering.check_frequency_mins",
+                "grow_settings.profiles[0].stages[0].watering_frequency_hours",
+                "data_logging_settings.frequency_mins",
+                "ventilation_settings.duration_mins",
+            ]
+        );
+
+        let mut config = Configuration::default();
+        config.controller_settings.watering_frequency_hours = 30;
+        config.data_logging_settings.retention.watering_days = Some(1);
+        let problems = config.validate().unwrap_err().problems;
+        assert_eq!(problems.len(), 1);
+        assert_eq!(
+            problems[0].path,
+            "data_logging_settings.retention.watering_days"
+        );
+
+        // Ventilation is turned off by a frequency of 0, whatever the duration
+        let mut config = Configuration::default();
+        config.ventilation_settings.frequency_mins = 0;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_amounts() {
+        let mut config = Configuration::default();
+        config.controller_settings.watering_amount_grams = 70_000;
+        config.controller_settings.watering_schedule[1].amount_grams = 70_000;
+        config.grow_settings.profiles[0].stages[0].watering_amount_grams = Some(70_000);
+
+        let paths = config
+            .validate()
+            .unwrap_err()
+            .problems
+            .into_iter()
+            .map(|problem| problem.path)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "controller_settings.watering_amount_grams",
+                "controller_settings.watering_schedule[1].amount_grams",
+                "grow_settings.profiles[0].stages[0].watering_amount_grams",
+            ]
+        );
+    }
+}
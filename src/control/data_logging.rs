@@ -1,9 +1,8 @@
 use std::time::Duration;
 
-use chrono::Utc;
 use serde::{Deserialize, Serialize};
 
-use crate::{sensors, state::ProgramStateShared};
+use crate::{sensors, state::ProgramStateShared, storage::Storage};
 
 #[derive(Serialize, Deserialize)]
 pub struct DataRecord {
@@ -12,31 +11,150 @@ pub struct DataRecord {
     pub soil_mositure: f32,
 }
 
+/// A reading of one sensor, so sensors can be added and removed without changing the columns
+#[derive(Serialize, Deserialize)]
+pub struct SensorRecord {
+    pub timestamp: i64,
+    pub sensor: String,
+    pub value: f32,
+}
+
+/// Length of the buckets logged samples are summarised in
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Bucket {
+    #[serde(rename = "5min")]
+    FiveMinutes,
+    #[serde(rename = "hour")]
+    Hour,
+    #[serde(rename = "day")]
+    Day,
+}
+
+impl Bucket {
+    pub fn seconds(self) -> i64 {
+        match self {
+            Bucket::FiveMinutes => 5 * 60,
+            Bucket::Hour => 60 * 60,
+            Bucket::Day => 24 * 60 * 60,
+        }
+    }
+
+    /// The name used in the API and in file names
+    pub fn name(self) -> &'static str {
+        match self {
+            Bucket::FiveMinutes => "5min",
+            Bucket::Hour => "hour",
+            Bucket::Day => "day",
+        }
+    }
+
+    /// Start of the bucket `timestamp` falls in, buckets are aligned to UTC
+    pub fn start(self, timestamp: i64) -> i64 {
+        timestamp - timestamp.rem_euclid(self.seconds())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct Aggregate {
+    pub min: f32,
+    pub avg: f32,
+    pub max: f32,
+}
+
+impl Aggregate {
+    fn of(values: impl Iterator<Item = f32>) -> Aggregate {
+        let (min, max, sum, count) = values.fold(
+            (f32::INFINITY, f32::NEG_INFINITY, 0., 0),
+            |(min, max, sum, count), value| {
+                (min.min(value), max.max(value), sum + value, count + 1)
+            },
+        );
+        Aggregate {
+            min,
+            avg: sum / count as f32,
+            max,
+        }
+    }
+}
+
+/// The samples logged during one bucket
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct BucketRecord {
+    /// Start of the bucket
+    pub timestamp: i64,
+    pub samples: usize,
+    pub temperature: Aggregate,
+    pub soil_moisture: Aggregate,
+}
+
+/// Summarises `records`, sorted by time, per bucket. Buckets without samples are left out.
+pub fn bucket_records(records: &[DataRecord], bucket: Bucket) -> Vec<BucketRecord> {
+    records
+        .chunk_by(|a, b| bucket.start(a.timestamp) == bucket.start(b.timestamp))
+        .map(|samples| BucketRecord {
+            timestamp: bucket.start(samples[0].timestamp),
+            samples: samples.len(),
+            temperature: Aggregate::of(samples.iter().map(|record| record.temperature)),
+            soil_moisture: Aggregate::of(samples.iter().map(|record| record.soil_mositure)),
+        })
+        .collect()
+}
+
+/// The buckets overlapping `from` to `to`, taken from the stored rollups as far as there are
+/// any, since the samples they were made of may have been removed
+pub fn summarise(
+    storage: &dyn Storage,
+    zone: &str,
+    from: i64,
+    to: i64,
+    bucket: Bucket,
+) -> anyhow::Result<Vec<BucketRecord>> {
+    let from = bucket.start(from);
+    let mut buckets = match bucket {
+        Bucket::FiveMinutes => Vec::new(),
+        Bucket::Hour | Bucket::Day => storage.rollups(zone, bucket, from, to)?,
+    };
+    let samples_from = buckets
+        .last()
+        .map_or(from, |last| last.timestamp + bucket.seconds());
+    let records = storage.data_records_between(zone, samples_from, to)?;
+    buckets.extend(bucket_records(&records, bucket));
+    Ok(buckets)
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct DataRecords {
     pub records: Vec<DataRecord>,
 }
 
-const FILE_PATH: &str = "./growpi.datalog.csv";
 impl DataRecords {
-    pub async fn push(program_state: ProgramStateShared) -> anyhow::Result<()> {
-        let program_state = program_state.lock().await;
-        let config = &program_state.config;
+    pub async fn push(program_state: ProgramStateShared, zone: usize) -> anyhow::Result<()> {
+        let mut program_state = program_state.lock().await;
+        let timestamp = program_state.clock.now().timestamp();
         let record = DataRecord {
-            timestamp: Utc::now().timestamp(),
-            temperature: sensors::get_temperature(config)?,
-            soil_mositure: sensors::get_soil_moisture(config)?,
+            timestamp,
+            temperature: sensors::get_temperature(&mut program_state, zone)?,
+            soil_mositure: sensors::get_soil_moisture(&mut program_state, zone)?,
         };
-        let mut writer = csv::WriterBuilder::new()
-            .has_headers(true)
-            .from_path(FILE_PATH)?;
-        writer.serialize(record)?;
-        writer.flush()?;
-        Ok(())
+        let sensor_records = sensors::read_sensors(&mut program_state, zone)?
+            .into_iter()
+            .map(|reading| SensorRecord {
+                timestamp,
+                sensor: reading.name,
+                value: reading.value,
+            })
+            .collect::<Vec<_>>();
+        let program_state = &mut *program_state;
+        let zone_name = &program_state.zones[zone].name;
+        program_state.storage.add_data_record(zone_name, &record)?;
+        program_state
+            .storage
+            .add_sensor_records(zone_name, &sensor_records)
     }
 }
 
-pub async fn data_logging_loop(program_state: ProgramStateShared) {
+pub async fn data_logging_loop(program_state: ProgramStateShared, zone: usize) {
+    let clock = program_state.lock().await.clock.clone();
     loop {
         let data_logging_settings = program_state
             .lock()
@@ -49,8 +167,47 @@ pub async fn data_logging_loop(program_state: ProgramStateShared) {
             data_logging_settings.frequency_mins,
         );
         if enabled {
-            let _ = DataRecords::push(program_state.clone()).await;
+            let _ = DataRecords::push(program_state.clone(), zone).await;
         }
-        tokio::time::sleep(Duration::from_mins(frequency_mins)).await;
+        clock.sleep(Duration::from_mins(frequency_mins)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_records() {
+        let sample = |timestamp, temperature| DataRecord {
+            timestamp,
+            temperature,
+            soil_mositure: 0.4,
+        };
+        let records = [
+            sample(3599, 20.),
+            sample(3600, 21.),
+            sample(4000, 24.),
+            sample(7199, 24.),
+            sample(10800, 22.),
+        ];
+        let buckets = bucket_records(&records, Bucket::Hour);
+        let starts = buckets
+            .iter()
+            .map(|bucket| bucket.timestamp)
+            .collect::<Vec<_>>();
+        assert_eq!(starts, [0, 3600, 10800]);
+        assert_eq!(buckets[1].samples, 3);
+        assert_eq!(
+            buckets[1].temperature,
+            Aggregate {
+                min: 21.,
+                avg: 23.,
+                max: 24.
+            }
+        );
+        assert_eq!(buckets[1].soil_moisture.avg, 0.4);
+        assert_eq!(bucket_records(&records, Bucket::Day).len(), 1);
+        assert_eq!(Bucket::FiveMinutes.start(-1), -300);
     }
 }
@@ -0,0 +1,22 @@
+use std::time::Duration;
+
+use crate::{grow, state::ProgramStateShared};
+
+/// Longest time the loop sleeps, so configuration changes are picked up eventually
+const MAX_SLEEP: Duration = Duration::from_hours(1);
+
+pub async fn grow_stage_loop(program_state: ProgramStateShared, zone: usize) {
+    loop {
+        let sleep_duration = {
+            let mut program_state = program_state.lock().await;
+            let _ = grow::ensure_started(&mut program_state, zone);
+            let _ = grow::advance_stage_if_due(&mut program_state, zone);
+            let now = program_state.clock.now();
+            grow::get_stage_end(&program_state, zone)
+                .and_then(|stage_end| (stage_end - now).to_std().ok())
+                .unwrap_or(MAX_SLEEP)
+                .min(MAX_SLEEP)
+        };
+        super::sleep_or_config_change(&program_state, sleep_duration).await;
+    }
+}
@@ -1,27 +1,73 @@
-use std::{path::Path, time::Duration};
+use std::{path::PathBuf, time::Duration};
 
-use crate::{io, state::ProgramStateShared};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
 
-pub const IMAGE_PATH: &str = "./growpi.image.jpeg";
+use crate::{
+    io::ImageResolution,
+    state::{ProgramState, ProgramStateShared},
+};
+
+/// A camera image, kept at `path` until `data_logging_settings.retention.image_days` removes it
+#[derive(Serialize, Deserialize)]
+pub struct ImageRecord {
+    pub timestamp: i64,
+    pub path: String,
+    pub resolution: ImageResolution,
+    pub size_bytes: u64,
+}
 
 pub async fn save_latest_image(program_state: ProgramStateShared) -> anyhow::Result<()> {
-    let resolution = program_state
-        .lock()
-        .await
+    let (resolution, camera, timestamp, path) = {
+        let program_state = program_state.lock().await;
+        let timestamp = program_state.clock.now().timestamp();
+        (
+            program_state
+                .config
+                .data_logging_settings
+                .imaging_resolution
+                .clone(),
+            program_state.camera.clone(),
+            timestamp,
+            program_state.paths.image(timestamp),
+        )
+    };
+
+    std::fs::create_dir_all(path.parent().context("Image path has no directory")?)?;
+    camera.capture(&resolution, &path).await?;
+    let mut program_state = program_state.lock().await;
+    let previous = latest_image(&program_state).ok();
+    let record = ImageRecord {
+        timestamp,
+        path: path.display().to_string(),
+        resolution,
+        size_bytes: std::fs::metadata(&path)?.len(),
+    };
+    program_state.storage.add_image(&record)?;
+    // Without a retention limit only the latest image file is kept, so images can't fill the disk
+    let keep_all = program_state
         .config
         .data_logging_settings
-        .imaging_resolution
-        .clone();
-
-    io::capture_image(&resolution, get_image_path()).await?;
+        .retention
+        .image_days
+        .is_some();
+    if let Some(previous) = previous.filter(|previous| !keep_all && *previous != path) {
+        if std::fs::exists(&previous)? {
+            std::fs::remove_file(&previous)?;
+        }
+    }
     Ok(())
 }
 
-pub fn get_image_path() -> &'static Path {
-    Path::new(IMAGE_PATH)
+/// The file of the last image taken
+pub fn latest_image(program_state: &ProgramState) -> anyhow::Result<PathBuf> {
+    let images = program_state.storage.images()?;
+    let latest = images.last().context("No image taken yet")?;
+    Ok(PathBuf::from(&latest.path))
 }
 
 pub async fn imaging_loop(program_state: ProgramStateShared) {
+    let clock = program_state.lock().await.clock.clone();
     loop {
         let imaging_frequency = match program_state
             .lock()
@@ -37,9 +83,30 @@ pub async fn imaging_loop(program_state: ProgramStateShared) {
         match imaging_frequency {
             Some(f) => {
                 let _ = save_latest_image(program_state.clone()).await;
-                tokio::time::sleep(Duration::from_mins(f)).await;
+                clock.sleep(Duration::from_mins(f)).await;
             }
-            None => tokio::time::sleep(Duration::from_hours(24)).await,
+            None => clock.sleep(Duration::from_hours(24)).await,
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Configuration, testing::TestState};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_latest_image_only() {
+        let mut config = Configuration::default();
+        config.data_logging_settings.imaging_resolution = ImageResolution::R360p;
+        let program_state = TestState::new("imaging", config);
+
+        save_latest_image(program_state.clone()).await.unwrap();
+        tokio::time::advance(Duration::from_secs(60)).await;
+        save_latest_image(program_state.clone()).await.unwrap();
+        let images = program_state.lock().await.storage.images().unwrap();
+        assert_eq!(images.len(), 2);
+        assert!(!std::fs::exists(&images[0].path).unwrap());
+        assert!(std::fs::exists(&images[1].path).unwrap());
+    }
+}
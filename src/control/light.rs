@@ -1,44 +1,181 @@
 use std::time::Duration;
 
-use chrono::{Local, Timelike};
+use chrono::{DateTime, Local, NaiveTime, TimeDelta};
 
-use crate::{actuators, state::ProgramStateShared};
+use crate::{
+    actuators::{self, Priority, RelayRequest, RequestSource},
+    clock,
+    config::ControllerSettings,
+    grow,
+    io::RelaySwitchState,
+    state::ProgramStateShared,
+};
 
-fn should_turn_on_light(on_hours: u64, lights_out: u64, current_hour: u64) -> bool {
-    let off_hours = 24 - on_hours;
-    !(lights_out..(lights_out + off_hours))
-        .map(|x| x % 24)
-        .any(|x| x == current_hour)
+/// Longest time the loop sleeps, so configuration changes are picked up eventually
+const MAX_SLEEP: Duration = Duration::from_hours(1);
+
+/// How often the brightness is updated while dimming up or down
+const DIMMING_STEP: TimeDelta = TimeDelta::minutes(1);
+
+/// The local time the lights go on and how long they stay on
+#[derive(Clone, Copy)]
+struct Photoperiod {
+    lights_on: NaiveTime,
+    duration: TimeDelta,
+}
+
+impl Photoperiod {
+    fn from_config(settings: &ControllerSettings) -> Photoperiod {
+        match &settings.photoperiod {
+            Some(photoperiod) => {
+                let mut duration = photoperiod.lights_off - photoperiod.lights_on;
+                if duration <= TimeDelta::zero() {
+                    duration += TimeDelta::days(1);
+                }
+                Photoperiod {
+                    lights_on: photoperiod.lights_on,
+                    duration,
+                }
+            }
+            None => {
+                let sunlight_hours = settings.sunlight_hours.min(24);
+                let lights_on_hour = (settings.lights_off_hour % 24 + 24 - sunlight_hours) % 24;
+                Photoperiod {
+                    lights_on: NaiveTime::from_hms_opt(lights_on_hour as u32, 0, 0)
+                        .unwrap_or_default(),
+                    duration: TimeDelta::hours(sunlight_hours as i64),
+                }
+            }
+        }
+    }
+
+    fn always_on(&self) -> bool {
+        self.duration >= TimeDelta::days(1)
+    }
+
+    fn always_off(&self) -> bool {
+        self.duration <= TimeDelta::zero()
+    }
+
+    /// When the lights last went on, at or before `now`
+    fn last_lights_on(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        [0, -1]
+            .into_iter()
+            .filter_map(|days| clock::local_time_on_day(now, self.lights_on, days))
+            .find(|time| *time <= now)
+    }
+
+    fn is_lit(&self, now: DateTime<Local>) -> bool {
+        if self.always_on() || self.always_off() {
+            return self.always_on();
+        }
+        self.last_lights_on(now)
+            .is_some_and(|lights_on| now < lights_on + self.duration)
+    }
+
+    /// The next time the lights go on or off, `None` if they never switch
+    fn next_transition(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        if self.always_on() || self.always_off() {
+            return None;
+        }
+        if self.is_lit(now) {
+            return self
+                .last_lights_on(now)
+                .map(|lights_on| lights_on + self.duration);
+        }
+        [0, 1]
+            .into_iter()
+            .filter_map(|days| clock::local_time_on_day(now, self.lights_on, days))
+            .find(|time| *time > now)
+    }
+
+    /// Brightness ramping up over `ramp` after the lights go on and down over `ramp` before they go off
+    fn brightness(&self, now: DateTime<Local>, ramp: TimeDelta) -> f32 {
+        if self.always_on() {
+            return 1.;
+        }
+        let Some(lights_on) = self.last_lights_on(now).filter(|_| self.is_lit(now)) else {
+            return 0.;
+        };
+        if ramp <= TimeDelta::zero() {
+            return 1.;
+        }
+        let since_on = now - lights_on;
+        let until_off = self.duration - since_on;
+        let closest = since_on.min(until_off);
+        (closest.num_milliseconds() as f32 / ramp.num_milliseconds() as f32).clamp(0., 1.)
+    }
 }
 
-async fn light_control(program_state: ProgramStateShared) -> anyhow::Result<()> {
-    let program_state = program_state.clone();
+/// Switches the lights of `zone` and returns how long to sleep until the next change
+async fn light_control(program_state: ProgramStateShared, zone: usize) -> anyhow::Result<Duration> {
     let mut program_state = program_state.lock().await;
 
-    let on_hours = program_state.config.controller_settings.sunlight_hours;
-    let current_hour = Local::now().time().hour() as u64;
-    let lights_out_hour = program_state.config.controller_settings.lights_off_hour;
-    if should_turn_on_light(on_hours, lights_out_hour, current_hour) {
-        actuators::switch_lights(crate::io::RelaySwitchState::On, &mut program_state)?;
-    } else {
-        actuators::switch_lights(crate::io::RelaySwitchState::Off, &mut program_state)?;
+    let now = program_state.clock.local_now();
+    let photoperiod = Photoperiod::from_config(&grow::controller_settings(&program_state, zone));
+    let lit = photoperiod.is_lit(now);
+    let state = match lit {
+        true => RelaySwitchState::On,
+        false => RelaySwitchState::Off,
+    };
+    let request = RelayRequest::new(
+        state,
+        Priority::Schedule,
+        RequestSource::LightControl,
+        "Photoperiod",
+    );
+    actuators::switch_lights(zone, request, &mut program_state)?;
+
+    let mut next_update = photoperiod.next_transition(now);
+    let ramp = TimeDelta::minutes(program_state.config.dimming_settings.ramp_mins as i64);
+    // There is one PWM dimmer, which dims the lights of the first zone
+    if let Some(dimmer) = program_state.dimmer.as_mut().filter(|_| zone == 0) {
+        let brightness = photoperiod.brightness(now, ramp);
+        dimmer.set_brightness(brightness)?;
+        if lit && brightness < 1. {
+            let next_step = now + DIMMING_STEP;
+            next_update = Some(next_update.map_or(next_step, |time| time.min(next_step)));
+        }
     }
 
-    Ok(())
+    let sleep_duration = next_update
+        .and_then(|time| (time - now).to_std().ok())
+        .unwrap_or(MAX_SLEEP)
+        .min(MAX_SLEEP);
+    Ok(sleep_duration)
 }
 
-pub async fn light_control_loop(program_state: ProgramStateShared) {
+pub async fn light_control_loop(program_state: ProgramStateShared, zone: usize) {
     loop {
-        let _ = light_control(program_state.clone()).await;
-        tokio::time::sleep(Duration::from_hours(1)).await;
+        let sleep_duration = light_control(program_state.clone(), zone)
+            .await
+            .unwrap_or(DIMMING_STEP.to_std().unwrap_or_default());
+        super::sleep_or_config_change(&program_state, sleep_duration).await;
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use chrono::TimeZone;
+
     use super::*;
+    use crate::config::{Configuration, PhotoperiodSettings};
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2024, 6, day, hour, minute, 0)
+            .unwrap()
+    }
+
     #[test]
     fn test_should_turn_on_light() {
+        let should_turn_on_light = |on_hours, lights_out, current_hour| {
+            let mut settings = Configuration::default().controller_settings;
+            settings.sunlight_hours = on_hours;
+            settings.lights_off_hour = lights_out;
+            Photoperiod::from_config(&settings).is_lit(at(1, current_hour, 0))
+        };
+
         assert!(should_turn_on_light(24, 5, 4));
         assert!(should_turn_on_light(24, 5, 5));
         assert!(should_turn_on_light(24, 5, 6));
@@ -62,4 +199,48 @@ mod tests {
 
         assert!(should_turn_on_light(24, 0, 13));
     }
+
+    #[test]
+    fn test_minute_photoperiod() {
+        let mut settings = Configuration::default().controller_settings;
+        settings.photoperiod = Some(PhotoperiodSettings {
+            lights_on: NaiveTime::from_hms_opt(6, 30, 0).unwrap(),
+            lights_off: NaiveTime::from_hms_opt(19, 0, 0).unwrap(),
+        });
+        let photoperiod = Photoperiod::from_config(&settings);
+
+        assert!(!photoperiod.is_lit(at(1, 6, 29)));
+        assert!(photoperiod.is_lit(at(1, 6, 30)));
+        assert!(photoperiod.is_lit(at(1, 18, 59)));
+        assert!(!photoperiod.is_lit(at(1, 19, 0)));
+
+        assert_eq!(photoperiod.next_transition(at(1, 5, 0)), Some(at(1, 6, 30)));
+        assert_eq!(
+            photoperiod.next_transition(at(1, 6, 30)),
+            Some(at(1, 19, 0))
+        );
+        assert_eq!(
+            photoperiod.next_transition(at(1, 20, 0)),
+            Some(at(2, 6, 30))
+        );
+
+        let ramp = TimeDelta::minutes(30);
+        assert_eq!(photoperiod.brightness(at(1, 6, 0), ramp), 0.);
+        assert_eq!(photoperiod.brightness(at(1, 6, 45), ramp), 0.5);
+        assert_eq!(photoperiod.brightness(at(1, 12, 0), ramp), 1.);
+        assert_eq!(photoperiod.brightness(at(1, 18, 51), ramp), 0.3);
+
+        // A photoperiod across midnight
+        settings.photoperiod = Some(PhotoperiodSettings {
+            lights_on: NaiveTime::from_hms_opt(22, 15, 0).unwrap(),
+            lights_off: NaiveTime::from_hms_opt(10, 45, 0).unwrap(),
+        });
+        let photoperiod = Photoperiod::from_config(&settings);
+        assert!(photoperiod.is_lit(at(2, 3, 0)));
+        assert!(!photoperiod.is_lit(at(2, 12, 0)));
+        assert_eq!(
+            photoperiod.next_transition(at(2, 3, 0)),
+            Some(at(2, 10, 45))
+        );
+    }
 }
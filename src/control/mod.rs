@@ -1,27 +1,121 @@
+use std::time::Duration;
+
 use crate::state::ProgramStateShared;
 
 use data_logging::data_logging_loop;
+use grow_stage::grow_stage_loop;
 use imaging::imaging_loop;
 use light::light_control_loop;
+use retention::compaction_loop;
 use soil::soil_moisture_control_loop;
-use temperature::temperature_control_loop;
-use tokio::join;
-use ventilation::ventilation_control_loop;
+use temperature::climate_control_loop;
+use tokio::task::JoinSet;
+use watchdog::watchdog_loop;
 
-mod data_logging;
+pub mod data_logging;
+mod grow_stage;
 pub mod imaging;
 mod light;
-mod soil;
+mod retention;
+pub mod soil;
 mod temperature;
-mod ventilation;
+mod watchdog;
 
 pub async fn control_thread(program_state: ProgramStateShared) {
-    join!(
-        ventilation_control_loop(program_state.clone()),
-        light_control_loop(program_state.clone()),
-        temperature_control_loop(program_state.clone()),
-        soil_moisture_control_loop(program_state.clone()),
-        data_logging_loop(program_state.clone()),
-        imaging_loop(program_state.clone())
-    );
+    let zone_count = program_state.lock().await.zones.len();
+    let mut loops = JoinSet::new();
+    for zone in 0..zone_count {
+        loops.spawn(grow_stage_loop(program_state.clone(), zone));
+        loops.spawn(light_control_loop(program_state.clone(), zone));
+        loops.spawn(climate_control_loop(program_state.clone(), zone));
+        loops.spawn(soil_moisture_control_loop(program_state.clone(), zone));
+        loops.spawn(data_logging_loop(program_state.clone(), zone));
+    }
+    loops.spawn(imaging_loop(program_state.clone()));
+    loops.spawn(watchdog_loop(program_state.clone()));
+    loops.spawn(compaction_loop(program_state.clone()));
+    while loops.join_next().await.is_some() {}
+}
+
+/// Sleeps for `duration`, waking up early when the configuration changes
+async fn sleep_or_config_change(program_state: &ProgramStateShared, duration: Duration) {
+    let (clock, config_changed) = {
+        let program_state = program_state.lock().await;
+        (
+            program_state.clock.clone(),
+            program_state.config_changed.clone(),
+        )
+    };
+    tokio::select! {
+        _ = clock.sleep(duration) => (),
+        _ = config_changed.notified() => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use chrono::{Local, TimeDelta, TimeZone, Timelike, Utc};
+
+    use super::*;
+    use crate::{
+        actuators,
+        clock::{Clock, ScaledClock},
+        config::Configuration,
+        io::RelaySwitchState,
+        testing::TestState,
+    };
+
+    #[tokio::test(start_paused = true)]
+    async fn test_control_loops_over_three_days() {
+        let mut config = Configuration::default();
+        config.data_logging_settings.enabled = false;
+        config.data_logging_settings.imaging_frequency_minutes = 0;
+        config.controller_settings.temperature_set_point_upper = 100.;
+        config.controller_settings.temperature_set_point_lower = -100.;
+        config.controller_settings.sunlight_hours = 18;
+        config.controller_settings.lights_off_hour = 0;
+        config.controller_settings.watering_frequency_hours = 30;
+        config.controller_settings.watering_amount_grams = 10;
+        config.ventilation_settings.frequency_mins = 30;
+        config.ventilation_settings.duration_mins = 3;
+
+        let start = Local
+            .with_ymd_and_hms(2024, 6, 1, 0, 0, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+        let clock = Arc::new(ScaledClock::new(start, 1.));
+        // Starting without any history, so the first watering happens right away
+        let program_state = TestState::with_clock("control_loops", config, clock.clone());
+        tokio::spawn(control_thread(program_state.clone()));
+
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        for minute in 0..3 * 24 * 60 {
+            let mut program_state = program_state.lock().await;
+            let light_state = actuators::get_light_state(&mut program_state, 0).unwrap();
+            let fan_state = actuators::get_fan_state(&mut program_state, 0).unwrap();
+            drop(program_state);
+
+            let lights_out = clock.local_now().hour() < 6;
+            assert_eq!(lights_out, light_state == RelaySwitchState::Off);
+            // Ventilation runs for 3 minutes every 30 minutes
+            let ventilating = minute % 30 < 3;
+            assert_eq!(ventilating, fan_state == RelaySwitchState::On);
+
+            tokio::time::sleep(Duration::from_mins(1)).await;
+        }
+
+        let program_state = program_state.lock().await;
+        let waterings = program_state.zones[0]
+            .history
+            .watering_records
+            .iter()
+            .map(|record| record.time)
+            .collect::<Vec<_>>();
+        let expected = [0, 30, 60]
+            .map(|hours| (start + TimeDelta::hours(hours)).timestamp())
+            .to_vec();
+        assert_eq!(waterings, expected);
+    }
 }
@@ -0,0 +1,186 @@
+use std::time::Duration;
+
+use chrono::TimeDelta;
+
+use crate::{
+    control::data_logging::{self, Bucket},
+    state::{ProgramState, ProgramStateShared},
+    storage::Storage,
+};
+
+const COMPACTION_INTERVAL: Duration = Duration::from_hours(1);
+
+/// Rolls up logged samples and removes data past `data_logging_settings.retention`
+pub async fn compaction_loop(program_state: ProgramStateShared) {
+    let clock = program_state.lock().await.clock.clone();
+    loop {
+        if let Err(e) = compact(&mut *program_state.lock().await) {
+            eprintln!("Could not compact the logged data: {:#}", e);
+        }
+        clock.sleep(COMPACTION_INTERVAL).await;
+    }
+}
+
+pub fn compact(program_state: &mut ProgramState) -> anyhow::Result<()> {
+    let now = program_state.clock.now().timestamp();
+    let retention = program_state.config.data_logging_settings.retention.clone();
+    let before = |days: u64| now - TimeDelta::days(days as i64).num_seconds();
+    let storage = program_state.storage.as_mut();
+    for zone in &mut program_state.zones {
+        // Rolled up first, so samples are only removed once they are part of a rollup
+        for bucket in [Bucket::Hour, Bucket::Day] {
+            roll_up(storage, &zone.name, bucket, now)?;
+        }
+        if let Some(days) = retention.raw_days {
+            storage.prune_samples(&zone.name, before(days))?;
+        }
+        if let Some(days) = retention.hourly_days {
+            storage.prune_rollups(&zone.name, Bucket::Hour, before(days))?;
+        }
+        if let Some(days) = retention.daily_days {
+            storage.prune_rollups(&zone.name, Bucket::Day, before(days))?;
+        }
+        if let Some(days) = retention.watering_days {
+            // Without the last watering, the next one would be due right away
+            let last_watering = zone
+                .history
+                .watering_records
+                .iter()
+                .map(|record| record.time)
+                .max();
+            let before = last_watering.map_or(before(days), |time| time.min(before(days)));
+            storage.prune_watering_records(&zone.name, before)?;
+            zone.history
+                .watering_records
+                .retain(|record| record.time >= before);
+        }
+    }
+    if let Some(days) = retention.image_days {
+        let removed = storage.prune_images(before(days))?;
+        let kept = storage.images()?;
+        for image in removed {
+            // Images taken before each got a file of its own share one, kept while it is in use
+            let in_use = kept.iter().any(|record| record.path == image.path);
+            if !in_use && std::fs::exists(&image.path)? {
+                std::fs::remove_file(&image.path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adds rollups for the buckets that ended since the last rollup
+fn roll_up(storage: &mut dyn Storage, zone: &str, bucket: Bucket, now: i64) -> anyhow::Result<()> {
+    let from = storage
+        .rollups(zone, bucket, i64::MIN, i64::MAX)?
+        .last()
+        .map_or(i64::MIN, |last| last.timestamp + bucket.seconds());
+    let until = bucket.start(now);
+    if from >= until {
+        return Ok(());
+    }
+    let records = storage.data_records_between(zone, from, until - 1)?;
+    storage.add_rollups(
+        zone,
+        bucket,
+        &data_logging::bucket_records(&records, bucket),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+
+    use super::*;
+    use crate::{
+        clock::ScaledClock,
+        config::Configuration,
+        control::{data_logging::DataRecord, imaging::ImageRecord},
+        history::{WateringReason, WateringRecord},
+        io::ImageResolution,
+        testing::TestState,
+    };
+
+    #[tokio::test]
+    async fn test_compact() {
+        let mut config = Configuration::default();
+        config.data_logging_settings.retention.raw_days = Some(1);
+        config.data_logging_settings.retention.watering_days = Some(30);
+        config.data_logging_settings.retention.image_days = Some(30);
+        let start = DateTime::<Utc>::from_timestamp(100 * 86400, 0).unwrap();
+        let clock = std::sync::Arc::new(ScaledClock::new(start, 1.));
+        let test_state = TestState::with_clock("retention", config, clock);
+        let paths = &test_state.paths;
+        let mut program_state = test_state.lock().await;
+
+        // Three days of samples every 30 minutes, up to now
+        let storage = program_state.storage.as_mut();
+        for timestamp in (97 * 86400..=100 * 86400).step_by(1800) {
+            let record = DataRecord {
+                timestamp,
+                temperature: 20.,
+                soil_mositure: 0.4,
+            };
+            storage.add_data_record("main", &record).unwrap();
+        }
+        for days_ago in [40, 35] {
+            let old_watering = WateringRecord::new(
+                start - TimeDelta::days(days_ago),
+                100,
+                0.3,
+                WateringReason::Interval,
+            );
+            program_state
+                .storage
+                .add_watering_record("main", &old_watering)
+                .unwrap();
+            program_state.zones[0]
+                .history
+                .watering_records
+                .push(old_watering);
+        }
+        std::fs::create_dir_all(paths.images_dir()).unwrap();
+        for days_ago in [40, 1] {
+            let timestamp = (start - TimeDelta::days(days_ago)).timestamp();
+            let path = paths.image(timestamp);
+            std::fs::write(&path, "jpeg").unwrap();
+            let image = ImageRecord {
+                timestamp,
+                path: path.display().to_string(),
+                resolution: ImageResolution::R360p,
+                size_bytes: 4,
+            };
+            program_state.storage.add_image(&image).unwrap();
+        }
+
+        compact(&mut program_state).unwrap();
+        compact(&mut program_state).unwrap();
+        let storage = program_state.storage.as_ref();
+        let raw = storage.data_records("main").unwrap();
+        assert_eq!(raw.first().unwrap().timestamp, 99 * 86400);
+        let hourly = storage
+            .rollups("main", Bucket::Hour, i64::MIN, i64::MAX)
+            .unwrap();
+        assert_eq!(hourly.len(), 72);
+        assert!(hourly.iter().all(|bucket| bucket.samples == 2));
+        let daily = storage
+            .rollups("main", Bucket::Day, i64::MIN, i64::MAX)
+            .unwrap();
+        assert_eq!(daily.len(), 3);
+        assert_eq!(daily[0].samples, 48);
+        // The last watering stays, however old it is
+        let last_watering = (start - TimeDelta::days(35)).timestamp();
+        let kept = storage.watering_records("main").unwrap();
+        assert_eq!(
+            kept.iter().map(|record| record.time).collect::<Vec<_>>(),
+            [last_watering]
+        );
+        assert_eq!(program_state.zones[0].history.watering_records.len(), 1);
+        // Each image has a file of its own, which goes with its record
+        let images = storage.images().unwrap();
+        assert_eq!(images.len(), 1);
+        assert!(std::fs::exists(&images[0].path).unwrap());
+        let old_image = paths.image((start - TimeDelta::days(40)).timestamp());
+        assert!(!std::fs::exists(old_image).unwrap());
+    }
+}
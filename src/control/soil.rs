@@ -1,44 +1,291 @@
 use std::time::Duration;
 
-use anyhow::bail;
-use chrono::{DateTime, Utc};
+use anyhow::{bail, Context};
+use chrono::{DateTime, Local, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
 
-use crate::{actuators, state::ProgramStateShared};
+use crate::{
+    actuators, clock,
+    config::{MoistureWateringSettings, WateringMode, WateringSlot},
+    grow,
+    history::{WateringReason, WateringRecord},
+    sensors,
+    state::{ProgramState, ProgramStateShared},
+};
 
-pub async fn soil_moisture_control_loop(program_state: ProgramStateShared) {
+/// Shortest time the loop sleeps, so a failing watering is not retried in a tight loop
+const MIN_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Scheduled watering slots missed by more than this, e.g. because of downtime, are skipped
+const SCHEDULE_GRACE_PERIOD: TimeDelta = TimeDelta::hours(1);
+
+#[derive(Serialize, Deserialize)]
+pub struct NextWatering {
+    pub time: i64,
+    pub amount: u64,
+}
+
+pub async fn soil_moisture_control_loop(program_state: ProgramStateShared, zone: usize) {
     loop {
-        let _ = soil_moisture_control(program_state.clone()).await;
-        let watering_frequency_hours = program_state
-            .lock()
-            .await
-            .config
-            .controller_settings
-            .watering_frequency_hours;
-        tokio::time::sleep(Duration::from_hours(watering_frequency_hours)).await;
-    }
-}
-
-async fn soil_moisture_control(program_state: ProgramStateShared) -> anyhow::Result<()> {
-    let mut program_state = program_state.lock().await;
-    let config = &program_state.config.controller_settings;
-    let watering_amount = config.watering_amount_grams;
-    let last_watering_time = program_state
-        .history
-        .watering_records
+        let _ = soil_moisture_control(program_state.clone(), zone).await;
+        let sleep_duration = {
+            let program_state = program_state.lock().await;
+            let config = grow::controller_settings(&program_state, zone);
+            let now = program_state.clock.now();
+            let next_check = match config.watering_mode {
+                WateringMode::MoistureThreshold => {
+                    now + TimeDelta::minutes(config.moisture_watering.check_frequency_mins as i64)
+                }
+                _ => get_next_watering(&program_state, zone)
+                    .and_then(|watering| DateTime::from_timestamp(watering.time, 0))
+                    .unwrap_or(now + TimeDelta::hours(1)),
+            };
+            (next_check - now)
+                .to_std()
+                .unwrap_or_default()
+                .max(MIN_CHECK_INTERVAL)
+        };
+        super::sleep_or_config_change(&program_state, sleep_duration).await;
+    }
+}
+
+/// The next watering planned by the interval or schedule mode, `None` when watering depends on the soil
+pub fn get_next_watering(program_state: &ProgramState, zone: usize) -> Option<NextWatering> {
+    let config = grow::controller_settings(program_state, zone);
+    let now = program_state.clock.now();
+    match config.watering_mode {
+        WateringMode::Interval => {
+            let time = last_watering_time(&program_state.zones[zone].history.watering_records)
+                .map(|time| time + TimeDelta::hours(config.watering_frequency_hours as i64))
+                .unwrap_or(now)
+                .max(now);
+            Some(NextWatering {
+                time: time.timestamp(),
+                amount: config.watering_amount_grams,
+            })
+        }
+        WateringMode::Schedule => {
+            next_scheduled_slot(&config.watering_schedule, program_state.clock.local_now()).map(
+                |(time, slot)| NextWatering {
+                    time: time.timestamp(),
+                    amount: slot.amount_grams,
+                },
+            )
+        }
+        WateringMode::MoistureThreshold => None,
+    }
+}
+
+async fn soil_moisture_control(
+    program_state: ProgramStateShared,
+    zone: usize,
+) -> anyhow::Result<()> {
+    let (watering_amount, reason) = {
+        let mut program_state = program_state.lock().await;
+        let config = grow::controller_settings(&program_state, zone);
+        let watering_amount = config.watering_amount_grams;
+        match config.watering_mode {
+            WateringMode::Interval => {
+                check_interval_watering(&program_state, zone)?;
+                (watering_amount, WateringReason::Interval)
+            }
+            WateringMode::MoistureThreshold => {
+                let moisture = sensors::get_soil_moisture(&mut program_state, zone)?;
+                check_moisture_watering(
+                    &config.moisture_watering,
+                    config.watering_amount_grams,
+                    &program_state.zones[zone].history.watering_records,
+                    moisture,
+                    program_state.clock.now(),
+                )?;
+                (watering_amount, WateringReason::MoistureThreshold)
+            }
+            WateringMode::Schedule => {
+                let slot = get_due_scheduled_slot(&program_state, zone)?;
+                (slot.amount_grams, WateringReason::Scheduled)
+            }
+        }
+    };
+    actuators::pump_water(
+        zone,
+        watering_amount
+            .try_into()
+            .with_context(|| format!("Can't water {}g at once", watering_amount))?,
+        reason,
+        &program_state,
+    )
+    .await?
+    .await??;
+    Ok(())
+}
+
+fn last_watering_time(watering_records: &[WateringRecord]) -> Option<DateTime<Utc>> {
+    watering_records
         .iter()
         .max_by_key(|x| x.time)
-        .and_then(|record| DateTime::from_timestamp(record.time, 0));
+        .and_then(|record| DateTime::from_timestamp(record.time, 0))
+}
+
+fn check_interval_watering(program_state: &ProgramState, zone: usize) -> anyhow::Result<()> {
+    let config = grow::controller_settings(program_state, zone);
+    let last_watering_time =
+        last_watering_time(&program_state.zones[zone].history.watering_records);
+    // Without any history this is the first watering, which is always due
     if let Some(last_watering_time) = last_watering_time {
-        let hours_passed = (Utc::now() - last_watering_time).num_hours();
-        if hours_passed as u64 <= config.watering_frequency_hours {
+        let time_passed = program_state.clock.now() - last_watering_time;
+        if time_passed < TimeDelta::hours(config.watering_frequency_hours as i64) {
             bail!("Watered too soon ago");
         }
-    } else {
-        bail!("Could not load last watering time");
     }
-    actuators::pump_water(
-        watering_amount.try_into().unwrap_or(100),
-        &mut program_state,
-    )?;
     Ok(())
 }
+
+/// Fails unless the soil is dry enough to water and watering stays within the configured limits
+fn check_moisture_watering(
+    settings: &MoistureWateringSettings,
+    watering_amount: u64,
+    watering_records: &[WateringRecord],
+    moisture: f32,
+    now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    if moisture >= settings.moisture_threshold {
+        bail!("Soil is moist enough");
+    }
+    // A watering after `now`, e.g. when the clock was set back, also counts as too soon
+    if let Some(last_watering_time) = last_watering_time(watering_records) {
+        let time_passed = now - last_watering_time;
+        if time_passed < TimeDelta::hours(settings.min_interval_hours as i64) {
+            bail!("Watered too soon ago");
+        }
+    }
+    let day_ago = (now - TimeDelta::days(1)).timestamp();
+    let watered_last_day: u64 = watering_records
+        .iter()
+        .filter(|record| record.time > day_ago)
+        .map(|record| record.amount)
+        .sum();
+    if watered_last_day + watering_amount > settings.max_daily_grams {
+        bail!("Daily maximum watering amount reached");
+    }
+    Ok(())
+}
+
+/// The most recently passed slot, unless it was already watered or missed by too much
+fn get_due_scheduled_slot(
+    program_state: &ProgramState,
+    zone: usize,
+) -> anyhow::Result<WateringSlot> {
+    let zone = &program_state.zones[zone];
+    let schedule = &zone.config.controller_settings.watering_schedule;
+    let now = program_state.clock.local_now();
+    let (slot_time, slot) =
+        previous_scheduled_slot(schedule, now).context("No watering schedule configured")?;
+    if now - slot_time > SCHEDULE_GRACE_PERIOD {
+        bail!("Missed the last scheduled watering");
+    }
+    let already_watered = zone.history.watering_records.iter().any(|record| {
+        matches!(record.reason, WateringReason::Scheduled) && record.time >= slot_time.timestamp()
+    });
+    if already_watered {
+        bail!("Scheduled watering already done");
+    }
+    Ok(slot.clone())
+}
+
+/// The first slot strictly after `now`
+fn next_scheduled_slot(
+    schedule: &[WateringSlot],
+    now: DateTime<Local>,
+) -> Option<(DateTime<Local>, &WateringSlot)> {
+    schedule
+        .iter()
+        .filter_map(|slot| {
+            [0, 1]
+                .into_iter()
+                .filter_map(|days| clock::local_time_on_day(now, slot.time, days))
+                .find(|time| *time > now)
+                .map(|time| (time, slot))
+        })
+        .min_by_key(|(time, _)| *time)
+}
+
+/// The last slot at or before `now`
+fn previous_scheduled_slot(
+    schedule: &[WateringSlot],
+    now: DateTime<Local>,
+) -> Option<(DateTime<Local>, &WateringSlot)> {
+    schedule
+        .iter()
+        .filter_map(|slot| {
+            [0, -1]
+                .into_iter()
+                .filter_map(|days| clock::local_time_on_day(now, slot.time, days))
+                .find(|time| *time <= now)
+                .map(|time| (time, slot))
+        })
+        .max_by_key(|(time, _)| *time)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveTime, TimeZone};
+
+    use super::*;
+
+    #[test]
+    fn test_check_moisture_watering() {
+        let settings = MoistureWateringSettings {
+            moisture_threshold: 0.3,
+            check_frequency_mins: 30,
+            min_interval_hours: 6,
+            max_daily_grams: 500,
+        };
+        let now = Utc::now();
+        let record = |hours_ago, amount| {
+            WateringRecord::new(
+                now - TimeDelta::hours(hours_ago),
+                amount,
+                0.2,
+                WateringReason::MoistureThreshold,
+            )
+        };
+
+        assert!(check_moisture_watering(&settings, 200, &[], 0.25, now).is_ok());
+        assert!(check_moisture_watering(&settings, 200, &[], 0.35, now).is_err());
+        assert!(check_moisture_watering(&settings, 200, &[record(2, 200)], 0.25, now).is_err());
+        assert!(check_moisture_watering(&settings, 200, &[record(7, 200)], 0.25, now).is_ok());
+        assert!(check_moisture_watering(&settings, 200, &[record(-2, 200)], 0.25, now).is_err());
+        let records = [record(20, 200), record(10, 200)];
+        assert!(check_moisture_watering(&settings, 200, &records, 0.25, now).is_err());
+        let records = [record(30, 200), record(10, 200)];
+        assert!(check_moisture_watering(&settings, 200, &records, 0.25, now).is_ok());
+    }
+
+    #[test]
+    fn test_scheduled_slots() {
+        let slot = |hour, amount_grams| WateringSlot {
+            time: NaiveTime::from_hms_opt(hour, 0, 0).unwrap(),
+            amount_grams,
+        };
+        let schedule = [slot(7, 100), slot(19, 150)];
+        let at = |day, hour, minute| {
+            Local
+                .with_ymd_and_hms(2024, 6, day, hour, minute, 0)
+                .unwrap()
+        };
+
+        let (time, slot) = next_scheduled_slot(&schedule, at(1, 6, 0)).unwrap();
+        assert_eq!((time, slot.amount_grams), (at(1, 7, 0), 100));
+        let (time, slot) = next_scheduled_slot(&schedule, at(1, 7, 0)).unwrap();
+        assert_eq!((time, slot.amount_grams), (at(1, 19, 0), 150));
+        let (time, slot) = next_scheduled_slot(&schedule, at(1, 20, 0)).unwrap();
+        assert_eq!((time, slot.amount_grams), (at(2, 7, 0), 100));
+
+        let (time, slot) = previous_scheduled_slot(&schedule, at(2, 6, 0)).unwrap();
+        assert_eq!((time, slot.amount_grams), (at(1, 19, 0), 150));
+        let (time, slot) = previous_scheduled_slot(&schedule, at(2, 7, 0)).unwrap();
+        assert_eq!((time, slot.amount_grams), (at(2, 7, 0), 100));
+
+        assert!(next_scheduled_slot(&[], at(1, 6, 0)).is_none());
+    }
+}
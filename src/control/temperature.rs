@@ -1,29 +1,343 @@
 use std::time::Duration;
 
-use crate::{actuators, sensors, state::ProgramStateShared};
+use chrono::{DateTime, TimeDelta, Utc};
 
-async fn temperature_control(program_state: ProgramStateShared) -> anyhow::Result<()> {
-    let mut program_state = program_state.lock().await;
-    let config = &program_state.config.controller_settings;
+use crate::{
+    actuators::{self, Priority, RelayRequest, RequestSource},
+    config::{ClimateSettings, Configuration, ControllerSettings},
+    grow,
+    io::RelaySwitchState,
+    sensors,
+    state::ProgramStateShared,
+};
+
+/// Keeps track of how long a relay has been in its current state
+#[derive(Clone, Copy)]
+struct SwitchTimer {
+    state: RelaySwitchState,
+    since: Option<DateTime<Utc>>,
+}
+
+impl SwitchTimer {
+    /// Switches to `state` unless the current state was held for less than its minimum time
+    fn request(
+        &mut self,
+        state: RelaySwitchState,
+        now: DateTime<Utc>,
+        min_on: TimeDelta,
+        min_off: TimeDelta,
+    ) -> RelaySwitchState {
+        if state != self.state {
+            let min_time = match self.state {
+                RelaySwitchState::On => min_on,
+                RelaySwitchState::Off => min_off,
+            };
+            let held_long_enough = self.since.is_none_or(|since| now - since >= min_time);
+            if held_long_enough {
+                self.state = state;
+                self.since = Some(now);
+            }
+        }
+        self.state
+    }
+}
+
+impl Default for SwitchTimer {
+    fn default() -> Self {
+        SwitchTimer {
+            state: RelaySwitchState::Off,
+            since: None,
+        }
+    }
+}
+
+const MIN_SWITCH_TIME_REASON: &str = "Holding the minimum switch time";
+
+/// Owns the fan and the heater, combining cooling, heating, dehumidifying and periodic ventilation
+#[derive(Default)]
+struct ClimateController {
+    cooling: bool,
+    heating: bool,
+    dehumidifying: bool,
+    next_ventilation: Option<DateTime<Utc>>,
+    ventilating_until: Option<DateTime<Utc>>,
+    fan: SwitchTimer,
+    heater: SwitchTimer,
+}
+
+struct ClimateOutput {
+    fan: RelaySwitchState,
+    fan_reason: &'static str,
+    heater: RelaySwitchState,
+    heater_reason: &'static str,
+}
+
+impl ClimateController {
+    fn update(
+        &mut self,
+        now: DateTime<Utc>,
+        temperature: Option<f32>,
+        humidity: Option<f32>,
+        config: &Configuration,
+        controller_settings: &ControllerSettings,
+    ) -> ClimateOutput {
+        let ClimateSettings {
+            hysteresis,
+            fan_min_on_secs,
+            fan_min_off_secs,
+            heater_min_on_secs,
+            heater_min_off_secs,
+            humidity_hysteresis,
+            ..
+        } = config.climate_settings;
+        let upper = controller_settings.temperature_set_point_upper;
+        let lower = controller_settings.temperature_set_point_lower;
+
+        // Without a reading, keep doing whatever was done before
+        if let Some(temperature) = temperature {
+            if temperature > upper {
+                self.cooling = true;
+            } else if temperature < upper - hysteresis {
+                self.cooling = false;
+            }
+            if temperature < lower {
+                self.heating = true;
+            } else if temperature > lower + hysteresis {
+                self.heating = false;
+            }
+        }
+        let heating = self.heating && config.relay_settings.heater_pin.is_some();
+        match (humidity, controller_settings.humidity_set_point_upper) {
+            (Some(humidity), Some(upper)) => {
+                if humidity > upper {
+                    self.dehumidifying = true;
+                } else if humidity < upper - humidity_hysteresis {
+                    self.dehumidifying = false;
+                }
+            }
+            (_, None) => self.dehumidifying = false,
+            (None, Some(_)) => {}
+        }
+
+        let ventilating = self.update_ventilation(now, config);
+
+        let seconds = |secs: u64| TimeDelta::seconds(secs as i64);
+        let wanted_fan = to_switch_state(self.cooling || self.dehumidifying || ventilating);
+        let fan = self.fan.request(
+            wanted_fan,
+            now,
+            seconds(fan_min_on_secs),
+            seconds(fan_min_off_secs),
+        );
+        let fan_reason = if fan != wanted_fan {
+            MIN_SWITCH_TIME_REASON
+        } else if self.cooling {
+            "Temperature above upper setpoint"
+        } else if self.dehumidifying {
+            "Humidity above upper setpoint"
+        } else if ventilating {
+            "Ventilation"
+        } else {
+            "Temperature below upper setpoint"
+        };
+        let wanted_heater = to_switch_state(heating);
+        let heater = self.heater.request(
+            wanted_heater,
+            now,
+            seconds(heater_min_on_secs),
+            seconds(heater_min_off_secs),
+        );
+        let heater_reason = if heater != wanted_heater {
+            MIN_SWITCH_TIME_REASON
+        } else if heating {
+            "Temperature below lower setpoint"
+        } else {
+            "Temperature above lower setpoint"
+        };
+        ClimateOutput {
+            fan,
+            fan_reason,
+            heater,
+            heater_reason,
+        }
+    }
+
+    fn update_ventilation(&mut self, now: DateTime<Utc>, config: &Configuration) -> bool {
+        let frequency = TimeDelta::minutes(config.ventilation_settings.frequency_mins as i64);
+        let duration = TimeDelta::minutes(config.ventilation_settings.duration_mins as i64);
+        if frequency.is_zero() || duration.is_zero() {
+            return false;
+        }
+        let mut next_ventilation = self.next_ventilation.unwrap_or(now);
+        if next_ventilation <= now {
+            self.ventilating_until = Some(next_ventilation + duration);
+            while next_ventilation <= now {
+                next_ventilation += frequency;
+            }
+        }
+        self.next_ventilation = Some(next_ventilation);
+        self.ventilating_until.is_some_and(|until| now < until)
+    }
+}
+
+fn to_switch_state(on: bool) -> RelaySwitchState {
+    match on {
+        true => RelaySwitchState::On,
+        false => RelaySwitchState::Off,
+    }
+}
 
-    let current_temperature = sensors::get_temperature(&program_state.config)?;
-    if current_temperature > config.temperature_set_point_upper {
-        actuators::switch_fan(crate::io::RelaySwitchState::On, &mut program_state)?;
-    } else if current_temperature < config.temperature_set_point_lower {
-        actuators::switch_fan(crate::io::RelaySwitchState::Off, &mut program_state)?;
+async fn climate_control(
+    program_state: ProgramStateShared,
+    zone: usize,
+    controller: &mut ClimateController,
+) -> anyhow::Result<()> {
+    let mut program_state = program_state.lock().await;
+    let temperature = sensors::get_temperature(&mut program_state, zone).ok();
+    let humidity_sensor = program_state.zones[zone]
+        .config
+        .climate_settings
+        .humidity_sensor
+        .clone();
+    let humidity =
+        humidity_sensor.and_then(|name| sensors::read_sensor(&mut program_state, zone, &name).ok());
+    let now = program_state.clock.now();
+    let controller_settings = grow::controller_settings(&program_state, zone);
+    let config = &program_state.zones[zone].config;
+    let output = controller.update(now, temperature, humidity, config, &controller_settings);
+    let has_heater = config.relay_settings.heater_pin.is_some();
+    let request = |state, reason| {
+        RelayRequest::new(
+            state,
+            Priority::Schedule,
+            RequestSource::ClimateControl,
+            reason,
+        )
+    };
+    actuators::switch_fan(
+        zone,
+        request(output.fan, output.fan_reason),
+        &mut program_state,
+    )?;
+    if has_heater {
+        actuators::switch_heater(
+            zone,
+            request(output.heater, output.heater_reason),
+            &mut program_state,
+        )?;
     }
     Ok(())
 }
 
-pub async fn temperature_control_loop(program_state: ProgramStateShared) {
+pub async fn climate_control_loop(program_state: ProgramStateShared, zone: usize) {
+    let mut controller = ClimateController::default();
     loop {
-        let loop_duration = program_state
-            .lock()
-            .await
-            .config
-            .controller_settings
-            .temperature_loop_mins;
-        let _ = temperature_control(program_state.clone()).await;
-        tokio::time::sleep(Duration::from_mins(loop_duration)).await;
+        let loop_duration = program_state.lock().await.config.climate_settings.loop_secs;
+        let _ = climate_control(program_state.clone(), zone, &mut controller).await;
+        super::sleep_or_config_change(&program_state, Duration::from_secs(loop_duration)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_climate_controller() {
+        let mut config = Configuration::default();
+        config.controller_settings.temperature_set_point_upper = 30.;
+        config.controller_settings.temperature_set_point_lower = 20.;
+        config.relay_settings.heater_pin = Some(3);
+        config.climate_settings.hysteresis = 1.;
+        config.climate_settings.fan_min_on_secs = 60;
+        config.climate_settings.fan_min_off_secs = 60;
+        config.climate_settings.heater_min_on_secs = 60;
+        config.climate_settings.heater_min_off_secs = 60;
+        config.ventilation_settings.frequency_mins = 0;
+
+        let mut controller = ClimateController::default();
+        let start = Utc::now();
+        let mut update = |secs, temperature| {
+            let output = controller.update(
+                start + TimeDelta::seconds(secs),
+                Some(temperature),
+                None,
+                &config,
+                &config.controller_settings,
+            );
+            (output.fan, output.heater)
+        };
+        use RelaySwitchState::{Off, On};
+
+        assert_eq!(update(0, 25.), (Off, Off));
+        assert_eq!(update(30, 30.5), (On, Off));
+        // Within the hysteresis, and then too soon after switching on
+        assert_eq!(update(60, 29.5), (On, Off));
+        assert_eq!(update(80, 28.), (On, Off));
+        assert_eq!(update(90, 28.), (Off, Off));
+        assert_eq!(update(200, 19.), (Off, On));
+        assert_eq!(update(300, 20.5), (Off, On));
+        assert_eq!(update(400, 21.5), (Off, Off));
+    }
+
+    #[test]
+    fn test_ventilation() {
+        let mut config = Configuration::default();
+        config.controller_settings.temperature_set_point_upper = 30.;
+        config.ventilation_settings.frequency_mins = 30;
+        config.ventilation_settings.duration_mins = 3;
+
+        let mut controller = ClimateController::default();
+        let start = Utc::now();
+        let mut fan_at = |mins| {
+            controller
+                .update(
+                    start + TimeDelta::minutes(mins),
+                    Some(25.),
+                    None,
+                    &config,
+                    &config.controller_settings,
+                )
+                .fan
+        };
+
+        assert_eq!(fan_at(0), RelaySwitchState::On);
+        assert_eq!(fan_at(2), RelaySwitchState::On);
+        assert_eq!(fan_at(3), RelaySwitchState::Off);
+        assert_eq!(fan_at(29), RelaySwitchState::Off);
+        assert_eq!(fan_at(30), RelaySwitchState::On);
+        assert_eq!(fan_at(34), RelaySwitchState::Off);
+    }
+
+    #[test]
+    fn test_dehumidifying() {
+        let mut config = Configuration::default();
+        config.controller_settings.temperature_set_point_upper = 30.;
+        config.controller_settings.humidity_set_point_upper = Some(70.);
+        config.climate_settings.humidity_hysteresis = 5.;
+        config.climate_settings.fan_min_on_secs = 0;
+        config.climate_settings.fan_min_off_secs = 0;
+        config.ventilation_settings.frequency_mins = 0;
+
+        let mut controller = ClimateController::default();
+        let start = Utc::now();
+        let mut update = |secs, humidity| {
+            let output = controller.update(
+                start + TimeDelta::seconds(secs),
+                Some(25.),
+                humidity,
+                &config,
+                &config.controller_settings,
+            );
+            (output.fan, output.fan_reason)
+        };
+        use RelaySwitchState::{Off, On};
+
+        assert_eq!(update(0, Some(60.)).0, Off);
+        assert_eq!(update(30, Some(75.)), (On, "Humidity above upper setpoint"));
+        // Within the hysteresis, and without a reading the fan keeps running
+        assert_eq!(update(60, Some(68.)).0, On);
+        assert_eq!(update(90, None).0, On);
+        assert_eq!(update(120, Some(64.)).0, Off);
     }
 }
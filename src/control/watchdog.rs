@@ -0,0 +1,130 @@
+use std::{collections::HashMap, time::Duration};
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+use crate::{
+    actuators::{self, Priority, RelayRequest, RequestSource},
+    config::DeviceKind,
+    io::RelaySwitchState,
+    safety,
+    state::ProgramStateShared,
+};
+
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When each device of each zone was first seen on, keyed by zone and device name
+type OnSince = HashMap<(usize, String), DateTime<Utc>>;
+
+/// Guards devices with a `max_on_secs`, like the pump, and hands relays back to the next
+/// request in line once requests expire
+pub async fn watchdog_loop(program_state: ProgramStateShared) {
+    let clock = program_state.lock().await.clock.clone();
+    let zone_count = program_state.lock().await.zones.len();
+    let mut on_since = OnSince::new();
+    loop {
+        for zone in 0..zone_count {
+            let _ = device_watchdog(program_state.clone(), zone, &mut on_since).await;
+        }
+        let _ = actuators::expire_relay_requests(&mut *program_state.lock().await);
+        clock.sleep(WATCHDOG_INTERVAL).await;
+    }
+}
+
+async fn device_watchdog(
+    program_state: ProgramStateShared,
+    zone: usize,
+    on_since: &mut OnSince,
+) -> anyhow::Result<()> {
+    let mut program_state = program_state.lock().await;
+    let now = program_state.clock.now();
+    let devices = actuators::zone_devices(&program_state.zones[zone].config);
+    for device in devices {
+        let Some(max_on_secs) = device.max_on_secs else {
+            continue;
+        };
+        let key = (zone, device.name.clone());
+        match program_state.relay.get_state(device.relay)? {
+            RelaySwitchState::Off => {
+                on_since.remove(&key);
+            }
+            RelaySwitchState::On => {
+                let on_since = *on_since.entry(key).or_insert(now);
+                let max_on_time = TimeDelta::seconds(max_on_secs as i64);
+                if now - on_since > max_on_time {
+                    // Hold the device off for a while, so whatever left it on can't restart it
+                    // right away
+                    let reason = format!(
+                        "Device {} of zone {} was on for more than {}s",
+                        device.name, program_state.zones[zone].name, max_on_secs
+                    );
+                    let request = RelayRequest::new(
+                        RelaySwitchState::Off,
+                        Priority::Safety,
+                        RequestSource::Watchdog,
+                        &reason,
+                    )
+                    .until(now + max_on_time);
+                    actuators::request_relay(device.relay, request, &mut program_state)?;
+                    // The pump run would otherwise count the water until its planned end
+                    if device.kind == DeviceKind::Pump
+                        && program_state.zones[zone].pump_run.is_some()
+                    {
+                        actuators::stop_pump(&mut program_state, zone)?;
+                    }
+                    safety::log_intervention(&format!("{}, switched it off", reason));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Configuration, history::WateringReason, testing::TestState};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pump_held_off() {
+        let program_state = TestState::new("watchdog", Configuration::default());
+
+        let pump_run = actuators::pump_for(
+            0,
+            Duration::from_secs(8),
+            WateringReason::Manual,
+            &program_state,
+        )
+        .await
+        .unwrap();
+        // Lowered while the pump runs, so the watchdog steps in after 2s
+        program_state.lock().await.zones[0]
+            .config
+            .water_pump_settings
+            .max_run_secs = 2;
+        let mut on_since = OnSince::new();
+        device_watchdog(program_state.clone(), 0, &mut on_since)
+            .await
+            .unwrap();
+        tokio::time::advance(Duration::from_secs(3)).await;
+        device_watchdog(program_state.clone(), 0, &mut on_since)
+            .await
+            .unwrap();
+        pump_run.await.unwrap().unwrap();
+
+        // Only the water pumped until the watchdog switched the pump off is recorded
+        let program_state = program_state.lock().await;
+        let grams_per_millisecond = program_state
+            .config
+            .water_pump_settings
+            .grams_per_millisecond;
+        let record = program_state.zones[0]
+            .history
+            .watering_records
+            .last()
+            .unwrap();
+        assert_eq!(
+            record.amount,
+            (3000. * grams_per_millisecond).round() as u64
+        );
+    }
+}
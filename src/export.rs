@@ -0,0 +1,99 @@
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::{
+    args::{ExportData, ExportFormat},
+    storage::Storage,
+};
+
+pub fn export(
+    storage: &dyn Storage,
+    zone: &str,
+    data: ExportData,
+    format: ExportFormat,
+    output: impl Write,
+) -> anyhow::Result<()> {
+    match data {
+        ExportData::History => write_records(&storage.watering_records(zone)?, format, output),
+        ExportData::Datalog => write_records(&storage.data_records(zone)?, format, output),
+        ExportData::Sensors => write_records(&storage.sensor_records(zone)?, format, output),
+        ExportData::Images => write_records(&storage.images()?, format, output),
+        ExportData::RelayEvents => {
+            write_records(&storage.relay_events(i64::MIN, i64::MAX)?, format, output)
+        }
+    }
+}
+
+fn write_records<T: Serialize>(
+    records: &[T],
+    format: ExportFormat,
+    mut output: impl Write,
+) -> anyhow::Result<()> {
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(true)
+                .from_writer(output);
+            for record in records {
+                writer.serialize(record)?;
+            }
+            writer.flush()?;
+        }
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut output, &records)?;
+            writeln!(output)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{
+        history::{WateringReason, WateringRecord},
+        storage::CsvStorage,
+        testing::TempDataDir,
+    };
+
+    #[test]
+    fn test_export_history() {
+        let paths = TempDataDir::new("export");
+        let record = WateringRecord::new(Utc::now(), 250, 0.4, WateringReason::Manual);
+        let mut storage = CsvStorage::new(paths.clone());
+        storage.add_watering_record("left", &record).unwrap();
+
+        let mut json = Vec::new();
+        export(
+            &storage,
+            "left",
+            ExportData::History,
+            ExportFormat::Json,
+            &mut json,
+        )
+        .unwrap();
+        let exported: Vec<WateringRecord> = serde_json::from_slice(&json).unwrap();
+        assert_eq!(exported.len(), 1);
+        assert_eq!(exported[0].time, record.time);
+
+        let mut csv = Vec::new();
+        export(
+            &storage,
+            "left",
+            ExportData::History,
+            ExportFormat::Csv,
+            &mut csv,
+        )
+        .unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        // The export leaves out the version marker of the time-series log
+        let file = std::fs::read_to_string(paths.history("left")).unwrap();
+        assert_eq!(
+            Some(csv.as_str()),
+            file.strip_prefix("#growpi-timeseries v1\n")
+        );
+    }
+}
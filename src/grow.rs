@@ -0,0 +1,242 @@
+use std::{io::Write, path::Path};
+
+use anyhow::{bail, Context};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Configuration, ControllerSettings, GrowProfile, GrowStage},
+    state::ProgramState,
+};
+
+/// Where the grow currently is, kept across restarts
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GrowProgress {
+    pub profile: String,
+    pub stage: usize,
+    pub stage_started: DateTime<Utc>,
+}
+
+impl GrowProgress {
+    /// Saves through a temporary file, so a power cut while saving keeps the previous stage
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let text = toml::to_string_pretty(self)?;
+        let temp_path = path.with_extension("toml.tmp");
+        let mut file = std::fs::File::create(&temp_path)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<GrowProgress> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&text)?)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StageProgress {
+    pub profile: String,
+    pub stage: usize,
+    pub stage_name: String,
+    pub stage_count: usize,
+    pub stage_started: i64,
+    /// `None` for stages that last until they are advanced by hand
+    pub stage_ends: Option<i64>,
+    pub days_in_stage: f32,
+}
+
+fn active_profile(config: &Configuration) -> Option<&GrowProfile> {
+    let name = config.grow_settings.active_profile.as_ref()?;
+    config
+        .grow_settings
+        .profiles
+        .iter()
+        .find(|profile| &profile.name == name)
+}
+
+/// The stage `zone` follows, if a profile is active
+fn active_stage(
+    program_state: &ProgramState,
+    zone: usize,
+) -> Option<(&GrowProfile, &GrowStage, &GrowProgress)> {
+    let zone = &program_state.zones[zone];
+    let profile = active_profile(&zone.config)?;
+    let progress = zone
+        .grow_progress
+        .as_ref()
+        .filter(|progress| progress.profile == profile.name)?;
+    let stage = profile.stages.get(progress.stage)?;
+    Some((profile, stage, progress))
+}
+
+fn stage_end(stage: &GrowStage, progress: &GrowProgress) -> Option<DateTime<Utc>> {
+    stage
+        .duration_days
+        .map(|days| progress.stage_started + TimeDelta::days(days as i64))
+}
+
+/// `controller_settings` of `zone` with the settings of its active grow stage applied
+pub fn controller_settings(program_state: &ProgramState, zone: usize) -> ControllerSettings {
+    let mut settings = program_state.zones[zone].config.controller_settings.clone();
+    if let Some((_, stage, _)) = active_stage(program_state, zone) {
+        apply_stage(&mut settings, stage);
+    }
+    settings
+}
+
+fn apply_stage(settings: &mut ControllerSettings, stage: &GrowStage) {
+    if let Some(sunlight_hours) = stage.sunlight_hours {
+        settings.sunlight_hours = sunlight_hours;
+    }
+    if let Some(lights_off_hour) = stage.lights_off_hour {
+        settings.lights_off_hour = lights_off_hour;
+    }
+    if let Some(photoperiod) = &stage.photoperiod {
+        settings.photoperiod = Some(photoperiod.clone());
+    }
+    if let Some(upper) = stage.temperature_set_point_upper {
+        settings.temperature_set_point_upper = upper;
+    }
+    if let Some(lower) = stage.temperature_set_point_lower {
+        settings.temperature_set_point_lower = lower;
+    }
+    if let Some(frequency) = stage.watering_frequency_hours {
+        settings.watering_frequency_hours = frequency;
+    }
+    if let Some(amount) = stage.watering_amount_grams {
+        settings.watering_amount_grams = amount;
+    }
+}
+
+/// Starts the active profile at its first stage, unless it is already being followed
+pub fn ensure_started(program_state: &mut ProgramState, zone: usize) -> anyhow::Result<()> {
+    let now = program_state.clock.now();
+    let path = program_state
+        .paths
+        .grow_stage(&program_state.zones[zone].name);
+    let zone = &mut program_state.zones[zone];
+    let Some(profile) = active_profile(&zone.config) else {
+        return Ok(());
+    };
+    let following = zone
+        .grow_progress
+        .as_ref()
+        .is_some_and(|progress| progress.profile == profile.name);
+    if !following {
+        let progress = GrowProgress {
+            profile: profile.name.clone(),
+            stage: 0,
+            stage_started: now,
+        };
+        progress.save(&path)?;
+        zone.grow_progress = Some(progress);
+    }
+    Ok(())
+}
+
+/// Moves on to the next stage once the current one has lasted its duration
+pub fn advance_stage_if_due(program_state: &mut ProgramState, zone: usize) -> anyhow::Result<bool> {
+    let now = program_state.clock.now();
+    let Some((profile, stage, progress)) = active_stage(program_state, zone) else {
+        return Ok(false);
+    };
+    let is_last_stage = progress.stage + 1 >= profile.stages.len();
+    match stage_end(stage, progress) {
+        Some(stage_end) if stage_end <= now && !is_last_stage => {
+            start_next_stage(program_state, zone, stage_end)?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Moves on to the next stage right away
+pub fn advance_stage(program_state: &mut ProgramState, zone: usize) -> anyhow::Result<()> {
+    let (profile, _, progress) =
+        active_stage(program_state, zone).context("No grow profile active")?;
+    let is_last_stage = progress.stage + 1 >= profile.stages.len();
+    if is_last_stage {
+        bail!("Already in the last stage");
+    }
+    start_next_stage(program_state, zone, program_state.clock.now())
+}
+
+fn start_next_stage(
+    program_state: &mut ProgramState,
+    zone: usize,
+    stage_started: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let path = program_state
+        .paths
+        .grow_stage(&program_state.zones[zone].name);
+    let progress = program_state.zones[zone]
+        .grow_progress
+        .as_mut()
+        .context("No grow profile active")?;
+    progress.stage += 1;
+    progress.stage_started = stage_started;
+    progress.save(&path)
+}
+
+/// When the active stage ends, if it ends by itself
+pub fn get_stage_end(program_state: &ProgramState, zone: usize) -> Option<DateTime<Utc>> {
+    let (_, stage, progress) = active_stage(program_state, zone)?;
+    stage_end(stage, progress)
+}
+
+pub fn get_stage_progress(program_state: &ProgramState, zone: usize) -> Option<StageProgress> {
+    let (profile, stage, progress) = active_stage(program_state, zone)?;
+    let time_in_stage = program_state.clock.now() - progress.stage_started;
+    Some(StageProgress {
+        profile: profile.name.clone(),
+        stage: progress.stage,
+        stage_name: stage.name.clone(),
+        stage_count: profile.stages.len(),
+        stage_started: progress.stage_started.timestamp(),
+        stage_ends: stage_end(stage, progress).map(|time| time.timestamp()),
+        days_in_stage: time_in_stage.num_minutes() as f32 / (24. * 60.),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::DEFAULT_ZONE, testing::TestState};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grow_stages() {
+        let mut config = Configuration::default();
+        config.controller_settings.sunlight_hours = 24;
+        config.grow_settings.active_profile = Some("Example".to_string());
+        let program_state = TestState::new("grow_stages", config);
+        let mut state = program_state.lock().await;
+
+        ensure_started(&mut state, 0).unwrap();
+        let progress = get_stage_progress(&state, 0).unwrap();
+        assert_eq!((progress.stage, progress.stage_count), (0, 3));
+        assert_eq!(controller_settings(&state, 0).sunlight_hours, 18);
+        assert_eq!(controller_settings(&state, 0).watering_amount_grams, 100);
+
+        drop(state);
+        tokio::time::advance(std::time::Duration::from_hours(14 * 24)).await;
+        let mut state = program_state.lock().await;
+        assert!(advance_stage_if_due(&mut state, 0).unwrap());
+        assert!(!advance_stage_if_due(&mut state, 0).unwrap());
+        let progress = get_stage_progress(&state, 0).unwrap();
+        assert_eq!(progress.stage_name, "Vegetative");
+        // Settings the stage leaves out come from controller_settings
+        assert_eq!(controller_settings(&state, 0).watering_amount_grams, 200);
+
+        advance_stage(&mut state, 0).unwrap();
+        assert_eq!(controller_settings(&state, 0).sunlight_hours, 12);
+        assert!(get_stage_progress(&state, 0).unwrap().stage_ends.is_none());
+        assert!(advance_stage(&mut state, 0).is_err());
+        let saved = GrowProgress::load(&program_state.paths.grow_stage(DEFAULT_ZONE)).unwrap();
+        assert_eq!(saved.stage, 2);
+
+        state.zones[0].config.grow_settings.active_profile = None;
+        assert_eq!(controller_settings(&state, 0).sunlight_hours, 24);
+    }
+}
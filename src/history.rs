@@ -1,52 +1,54 @@
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::storage::Storage;
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub enum WateringReason {
+    /// Recorded before reasons were tracked
+    #[default]
+    Unknown,
+    Manual,
+    Interval,
+    MoistureThreshold,
+    Scheduled,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct WateringRecord {
     pub time: i64,
     pub amount: u64,
     pub moisture_before_watering: f32,
+    #[serde(default)]
+    pub reason: WateringReason,
 }
 
 impl WateringRecord {
-    pub fn new(amount: u64, moisture_before_watering: f32) -> WateringRecord {
+    pub fn new(
+        time: DateTime<Utc>,
+        amount: u64,
+        moisture_before_watering: f32,
+        reason: WateringReason,
+    ) -> WateringRecord {
         WateringRecord {
-            time: Utc::now().timestamp(),
+            time: time.timestamp(),
             amount,
             moisture_before_watering,
+            reason,
         }
     }
 }
 
+/// The waterings of a zone, kept in memory for the control loops and added to the storage
 #[derive(Default)]
 pub struct History {
     pub watering_records: Vec<WateringRecord>,
 }
 
-const FILE_PATH: &str = "./growpi.history.csv";
-
 impl History {
-    pub fn save(&self) -> anyhow::Result<()> {
-        let mut writer = csv::WriterBuilder::new()
-            .has_headers(true)
-            .from_path(FILE_PATH)?;
-        for record in &self.watering_records {
-            writer.serialize(record)?;
-        }
-        writer.flush()?;
-        Ok(())
-    }
-
-    pub fn load() -> anyhow::Result<History> {
-        let mut history = csv::ReaderBuilder::new()
-            .has_headers(true)
-            .from_path(FILE_PATH)?;
-        let mut result = Vec::new();
-        for record in history.deserialize() {
-            result.push(record?);
-        }
+    pub fn load(storage: &dyn Storage, zone: &str) -> anyhow::Result<History> {
         Ok(History {
-            watering_records: result,
+            watering_records: storage.watering_records(zone)?,
         })
     }
 }
@@ -57,15 +59,20 @@ mod tests {
     use chrono::Local;
 
     use super::*;
+    use crate::{config::DEFAULT_ZONE, storage::CsvStorage, testing::TempDataDir};
 
     #[test]
     fn test_write_default() {
-        let mut history = History::default();
-        history.watering_records.push(WateringRecord {
+        let paths = TempDataDir::new("history");
+        let mut storage = CsvStorage::new(paths.clone());
+        let record = WateringRecord {
             time: Local::now().timestamp(),
             amount: 456,
             moisture_before_watering: 71.1,
-        });
-        history.save().unwrap();
+            reason: WateringReason::Manual,
+        };
+        storage.add_watering_record(DEFAULT_ZONE, &record).unwrap();
+        let history = History::load(&storage, DEFAULT_ZONE).unwrap();
+        assert_eq!(history.watering_records.last().unwrap().time, record.time);
     }
 }
@@ -0,0 +1,195 @@
+use ads1x1x::{Ads1x1x, ChannelSelection, DynamicOneShot};
+use anyhow::{anyhow, bail, Context};
+use async_process::Command;
+use nb::block;
+use rppal::{
+    gpio::{Gpio, Level, OutputPin},
+    pwm::{self, Channel, Pwm},
+};
+
+use super::{
+    configured_pins, AnalogInput, BoxFuture, Camera, Dimmer, ImageResolution, RelayBank,
+    RelaySwitchState,
+};
+use crate::config::{Configuration, DimmingSettings, Polarity};
+
+pub struct Ads1115;
+
+impl AnalogInput for Ads1115 {
+    fn read_voltage(&mut self, pin: u8) -> anyhow::Result<f32> {
+        const ADS1115_DEFAULT_RANGE: f32 = 4.096;
+
+        let adc = rppal::i2c::I2c::new()?;
+        let mut adc = Ads1x1x::new_ads1115(adc, ads1x1x::SlaveAddr::Alternative(false, false));
+        adc.set_full_scale_range(ads1x1x::FullScaleRange::Within4_096V)
+            .map_err(|_| anyhow!("Couldn't set full scale range"))?;
+        let channel: ChannelSelection = match pin {
+            0 => ChannelSelection::SingleA0,
+            1 => ChannelSelection::SingleA1,
+            2 => ChannelSelection::SingleA2,
+            3 => ChannelSelection::SingleA3,
+            _ => bail!("Pin {} not available. Only 0-3", pin),
+        };
+        let result = block!(adc.read(channel)).map_err(|e| anyhow!("{:?}", e))?;
+        let result = result as f32;
+        let result = result / i16::MAX as f32 * ADS1115_DEFAULT_RANGE;
+        Ok(result)
+    }
+}
+
+pub struct GpioRelayBank {
+    relay_pins: Vec<Option<OutputPin>>,
+    polarities: Vec<Polarity>,
+}
+
+impl GpioRelayBank {
+    pub fn new(config: &Configuration) -> anyhow::Result<GpioRelayBank> {
+        let mut output_pins = config
+            .relay_settings
+            .relay_gpio_pins
+            .clone()
+            .into_iter()
+            .map(|pin| {
+                match pin {
+                    -1 => None,
+                    _ => Some(pin as u8),
+                }
+                .and_then(|pin| {
+                    let result = (|| -> anyhow::Result<OutputPin> {
+                        Ok(Gpio::new()?.get(pin)?.into_output())
+                    })();
+                    result.ok()
+                })
+            })
+            .collect::<Vec<_>>();
+        let polarities = config.relay_polarities();
+        for (pin, polarity) in output_pins.iter_mut().zip(&polarities) {
+            if let Some(pin) = pin {
+                pin.write(off_level(*polarity));
+            }
+        }
+        Ok(GpioRelayBank {
+            relay_pins: output_pins,
+            polarities,
+        })
+    }
+
+    fn polarity(&self, pin: u8) -> Polarity {
+        self.polarities
+            .get(pin as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn get_output_pin(&mut self, pin: u8) -> anyhow::Result<&mut OutputPin> {
+        self.relay_pins
+            .get_mut(pin as usize)
+            .context(format!("Pin {} not within pin array", pin,))?
+            .as_mut()
+            .context("Pin not configured.")
+    }
+}
+
+/// The level that keeps a relay off, active-low relays turn on on LOW
+fn off_level(polarity: Polarity) -> Level {
+    match polarity {
+        Polarity::ActiveLow => Level::High,
+        Polarity::ActiveHigh => Level::Low,
+    }
+}
+
+impl RelayBank for GpioRelayBank {
+    fn set_state(&mut self, pin: u8, state: RelaySwitchState) -> anyhow::Result<()> {
+        let off_level = off_level(self.polarity(pin));
+        let pin = self.get_output_pin(pin)?;
+        match state {
+            RelaySwitchState::On => pin.write(!off_level),
+            RelaySwitchState::Off => pin.write(off_level),
+        }
+        Ok(())
+    }
+
+    fn get_state(&mut self, pin: u8) -> anyhow::Result<RelaySwitchState> {
+        let off_level = off_level(self.polarity(pin));
+        let pin = self.get_output_pin(pin)?;
+        if pin.is_set_high() == (off_level == Level::High) {
+            Ok(RelaySwitchState::Off)
+        } else {
+            Ok(RelaySwitchState::On)
+        }
+    }
+
+    fn configured_pins(&self) -> Vec<u8> {
+        configured_pins(&self.relay_pins)
+    }
+}
+
+pub struct PwmDimmer {
+    pwm: Pwm,
+    brightness: f32,
+}
+
+impl PwmDimmer {
+    pub fn new(settings: &DimmingSettings) -> anyhow::Result<PwmDimmer> {
+        let channel = match settings.pwm_channel {
+            0 => Channel::Pwm0,
+            1 => Channel::Pwm1,
+            _ => bail!(
+                "PWM channel {} not available. Only 0-1",
+                settings.pwm_channel
+            ),
+        };
+        let pwm = Pwm::with_frequency(
+            channel,
+            settings.frequency_hz,
+            0.,
+            pwm::Polarity::Normal,
+            true,
+        )?;
+        Ok(PwmDimmer {
+            pwm,
+            brightness: 0.,
+        })
+    }
+}
+
+impl Dimmer for PwmDimmer {
+    fn set_brightness(&mut self, brightness: f32) -> anyhow::Result<()> {
+        let brightness = brightness.clamp(0., 1.);
+        self.pwm.set_duty_cycle(brightness.into())?;
+        self.brightness = brightness;
+        Ok(())
+    }
+
+    fn brightness(&self) -> f32 {
+        self.brightness
+    }
+}
+
+pub struct LibCamera;
+
+impl Camera for LibCamera {
+    fn capture<'a>(
+        &'a self,
+        resolution: &'a ImageResolution,
+        path: &'a std::path::Path,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let path = std::path::absolute(path)?;
+            let (width, height) = resolution.get_width_height();
+            Command::new("/usr/bin/libcamera-jpeg")
+                .arg("-o")
+                .arg(path.clone())
+                .arg("-t")
+                .arg("1")
+                .arg("--width")
+                .arg(width.to_string())
+                .arg("--height")
+                .arg(height.to_string())
+                .status()
+                .await?
+                .exit_ok()?;
+            Ok(())
+        })
+    }
+}
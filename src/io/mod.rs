@@ -0,0 +1,217 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, TryLockError},
+};
+
+use anyhow::bail;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{clock::SharedClock, config::*, simulation::GrowBucket};
+
+mod hardware;
+mod simulated;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+pub trait AnalogInput: Send {
+    fn read_voltage(&mut self, pin: u8) -> anyhow::Result<f32>;
+}
+
+pub trait RelayBank: Send {
+    fn set_state(&mut self, pin: u8, state: RelaySwitchState) -> anyhow::Result<()>;
+    fn get_state(&mut self, pin: u8) -> anyhow::Result<RelaySwitchState>;
+    fn configured_pins(&self) -> Vec<u8>;
+}
+
+/// Sets the brightness of dimmable lights, from 0 for dark to 1 for full brightness
+pub trait Dimmer: Send {
+    fn set_brightness(&mut self, brightness: f32) -> anyhow::Result<()>;
+    fn brightness(&self) -> f32;
+}
+
+pub trait Camera: Send + Sync {
+    fn capture<'a>(
+        &'a self,
+        resolution: &'a ImageResolution,
+        path: &'a std::path::Path,
+    ) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+pub struct Io {
+    pub relay: Relay,
+    pub analog: Box<dyn AnalogInput>,
+    pub dimmer: Option<Box<dyn Dimmer>>,
+    pub camera: Arc<dyn Camera>,
+}
+
+pub fn init_io(config: &Configuration, clock: SharedClock) -> anyhow::Result<Io> {
+    let io = match config.io_settings.backend {
+        IoBackend::Hardware => Io {
+            relay: Relay::new(Box::new(hardware::GpioRelayBank::new(config)?)),
+            analog: Box::new(hardware::Ads1115),
+            dimmer: match config.dimming_settings.enabled {
+                true => Some(Box::new(hardware::PwmDimmer::new(
+                    &config.dimming_settings,
+                )?)),
+                false => None,
+            },
+            camera: Arc::new(hardware::LibCamera),
+        },
+        IoBackend::Simulated => {
+            // Every zone is a bucket of its own, driven by its relays and read by its sensors
+            let grow_buckets = match config.simulation_settings.grow_bucket_model {
+                true => config
+                    .resolve_zones()
+                    .iter()
+                    .map(|(_, config)| Arc::new(Mutex::new(GrowBucket::new(config, clock.clone()))))
+                    .collect(),
+                false => Vec::new(),
+            };
+            Io {
+                relay: Relay::new(Box::new(simulated::SimulatedRelayBank::new(
+                    config,
+                    grow_buckets.clone(),
+                ))),
+                analog: Box::new(simulated::SimulatedAnalogInput::new(config, grow_buckets)),
+                dimmer: config
+                    .dimming_settings
+                    .enabled
+                    .then(|| Box::new(simulated::SimulatedDimmer::default()) as Box<dyn Dimmer>),
+                camera: Arc::new(simulated::SimulatedCamera),
+            }
+        }
+    };
+    Ok(io)
+}
+
+type SharedRelayBank = Arc<Mutex<Box<dyn RelayBank>>>;
+
+pub struct Relay {
+    bank: SharedRelayBank,
+}
+
+/// Gives access to the relays from places that cannot wait for the program state, like the panic hook
+#[derive(Clone)]
+pub struct RelaySafetyHandle {
+    bank: SharedRelayBank,
+}
+#[derive(Clone, Copy, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+pub enum RelaySwitchState {
+    On,
+    Off,
+}
+impl Relay {
+    pub fn new(bank: Box<dyn RelayBank>) -> Relay {
+        Relay {
+            bank: Arc::new(Mutex::new(bank)),
+        }
+    }
+
+    fn bank(&self) -> std::sync::MutexGuard<'_, Box<dyn RelayBank>> {
+        // The bank holds no invariants a panic could break, so a poisoned lock is still usable
+        self.bank.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn switch(&mut self, pin: u8, state: RelaySwitchState) -> anyhow::Result<()> {
+        self.bank().set_state(pin, state)
+    }
+
+    pub fn get_state(&mut self, pin: u8) -> anyhow::Result<RelaySwitchState> {
+        self.bank().get_state(pin)
+    }
+
+    pub fn configured_pins(&self) -> Vec<u8> {
+        self.bank().configured_pins()
+    }
+
+    /// Switches every relay off, returning the pins that were on
+    pub fn all_off(&mut self) -> anyhow::Result<Vec<u8>> {
+        all_off(&mut **self.bank())
+    }
+
+    /// Drops the relay bank, releasing its GPIO pins, until `take_bank` brings in another one
+    pub fn release_bank(&mut self) {
+        *self.bank() = Box::new(ReleasedRelayBank);
+    }
+
+    /// Moves the bank of `other` into this relay, so existing safety handles switch it too
+    pub fn take_bank(&mut self, other: Relay) {
+        let bank = std::mem::replace(&mut *other.bank(), Box::new(ReleasedRelayBank));
+        *self.bank() = bank;
+    }
+
+    pub fn safety_handle(&self) -> RelaySafetyHandle {
+        RelaySafetyHandle {
+            bank: self.bank.clone(),
+        }
+    }
+}
+
+impl RelaySafetyHandle {
+    /// Switches every relay off without blocking, failing if the relays are in use
+    ///
+    /// Returns the pins that were on.
+    pub fn all_off(&self) -> anyhow::Result<Vec<u8>> {
+        let mut bank = match self.bank.try_lock() {
+            Ok(bank) => bank,
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(TryLockError::WouldBlock) => bail!("Relays are in use"),
+        };
+        all_off(&mut **bank)
+    }
+}
+
+/// Stands in for a relay bank while it is being re-initialised
+struct ReleasedRelayBank;
+
+impl RelayBank for ReleasedRelayBank {
+    fn set_state(&mut self, _pin: u8, _state: RelaySwitchState) -> anyhow::Result<()> {
+        bail!("Relays are being re-initialised")
+    }
+    fn get_state(&mut self, _pin: u8) -> anyhow::Result<RelaySwitchState> {
+        bail!("Relays are being re-initialised")
+    }
+    fn configured_pins(&self) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+fn configured_pins<T>(pins: &[Option<T>]) -> Vec<u8> {
+    pins.iter()
+        .enumerate()
+        .filter(|(_, pin)| pin.is_some())
+        .map(|(index, _)| index as u8)
+        .collect()
+}
+
+fn all_off(bank: &mut dyn RelayBank) -> anyhow::Result<Vec<u8>> {
+    let mut on_pins = Vec::new();
+    for pin in bank.configured_pins() {
+        if bank.get_state(pin).ok() == Some(RelaySwitchState::On) {
+            on_pins.push(pin);
+        }
+        bank.set_state(pin, RelaySwitchState::Off)?;
+    }
+    Ok(on_pins)
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Serialize, Deserialize)]
+pub enum ImageResolution {
+    R1080p,
+    R720p,
+    R480p,
+    R360p,
+}
+impl ImageResolution {
+    fn get_width_height(&self) -> (u64, u64) {
+        match self {
+            ImageResolution::R1080p => (1920, 1080),
+            ImageResolution::R720p => (1280, 720),
+            ImageResolution::R480p => (640, 480),
+            ImageResolution::R360p => (480, 360),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Context};
+use jpeg_encoder::{ColorType, Encoder};
+
+use super::{
+    configured_pins, AnalogInput, BoxFuture, Camera, Dimmer, ImageResolution, RelayBank,
+    RelaySwitchState,
+};
+use crate::{config::Configuration, simulation::GrowBucket};
+
+pub type SharedGrowBucket = Arc<Mutex<GrowBucket>>;
+
+pub struct SimulatedAnalogInput {
+    voltages: Vec<f32>,
+    grow_buckets: Vec<SharedGrowBucket>,
+}
+
+impl SimulatedAnalogInput {
+    pub fn new(
+        config: &Configuration,
+        grow_buckets: Vec<SharedGrowBucket>,
+    ) -> SimulatedAnalogInput {
+        SimulatedAnalogInput {
+            voltages: config.simulation_settings.channel_voltages.to_vec(),
+            grow_buckets,
+        }
+    }
+}
+
+impl AnalogInput for SimulatedAnalogInput {
+    fn read_voltage(&mut self, pin: u8) -> anyhow::Result<f32> {
+        let Some(voltage) = self.voltages.get(pin as usize) else {
+            bail!("Pin {} not available. Only 0-3", pin);
+        };
+        for grow_bucket in &self.grow_buckets {
+            let mut grow_bucket = grow_bucket
+                .lock()
+                .map_err(|_| anyhow!("Grow bucket model poisoned"))?;
+            if let Some(voltage) = grow_bucket.read_voltage(pin) {
+                return Ok(voltage);
+            }
+        }
+        Ok(*voltage)
+    }
+}
+
+#[derive(Default)]
+pub struct SimulatedDimmer {
+    brightness: f32,
+}
+
+impl Dimmer for SimulatedDimmer {
+    fn set_brightness(&mut self, brightness: f32) -> anyhow::Result<()> {
+        self.brightness = brightness.clamp(0., 1.);
+        Ok(())
+    }
+
+    fn brightness(&self) -> f32 {
+        self.brightness
+    }
+}
+
+pub struct SimulatedRelayBank {
+    relay_states: Vec<Option<RelaySwitchState>>,
+    grow_buckets: Vec<SharedGrowBucket>,
+}
+
+impl SimulatedRelayBank {
+    pub fn new(config: &Configuration, grow_buckets: Vec<SharedGrowBucket>) -> SimulatedRelayBank {
+        let relay_states = config
+            .relay_settings
+            .relay_gpio_pins
+            .iter()
+            .map(|pin| match pin {
+                -1 => None,
+                _ => Some(RelaySwitchState::Off),
+            })
+            .collect();
+        SimulatedRelayBank {
+            relay_states,
+            grow_buckets,
+        }
+    }
+
+    fn get_relay_state(&mut self, pin: u8) -> anyhow::Result<&mut RelaySwitchState> {
+        self.relay_states
+            .get_mut(pin as usize)
+            .context(format!("Pin {} not within pin array", pin,))?
+            .as_mut()
+            .context("Pin not configured.")
+    }
+}
+
+impl RelayBank for SimulatedRelayBank {
+    fn set_state(&mut self, pin: u8, state: RelaySwitchState) -> anyhow::Result<()> {
+        *self.get_relay_state(pin)? = state;
+        for grow_bucket in &self.grow_buckets {
+            grow_bucket
+                .lock()
+                .map_err(|_| anyhow!("Grow bucket model poisoned"))?
+                .set_relay(pin, state);
+        }
+        Ok(())
+    }
+
+    fn get_state(&mut self, pin: u8) -> anyhow::Result<RelaySwitchState> {
+        Ok(*self.get_relay_state(pin)?)
+    }
+
+    fn configured_pins(&self) -> Vec<u8> {
+        configured_pins(&self.relay_states)
+    }
+}
+
+pub struct SimulatedCamera;
+
+impl Camera for SimulatedCamera {
+    fn capture<'a>(
+        &'a self,
+        resolution: &'a ImageResolution,
+        path: &'a std::path::Path,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let (width, height) = resolution.get_width_height();
+            // A green gradient, so it is obvious that the image did not come from a camera
+            let mut pixels = Vec::with_capacity((width * height * 3) as usize);
+            for y in 0..height {
+                for x in 0..width {
+                    pixels.push((x * 0x40 / width) as u8);
+                    pixels.push((0x60 + y * 0x80 / height) as u8);
+                    pixels.push(0x30);
+                }
+            }
+            let encoder = Encoder::new_file(path, 80)?;
+            encoder.encode(&pixels, width as u16, height as u16, ColorType::Rgb)?;
+            Ok(())
+        })
+    }
+}
@@ -1,51 +1,129 @@
 #![feature(duration_constructors)]
 #![feature(exit_status_error)]
 
+use std::path::Path;
+
+use args::{Args, Command};
+use clap::Parser;
 use cli_mode::run_cli;
 use config::Configuration;
 use server::run_server;
 use state::init_state;
 
 mod actuators;
+mod args;
+mod calibrate;
 mod cli_mode;
+mod clock;
 mod config;
 mod control;
+mod export;
+mod grow;
 mod history;
 mod io;
+mod paths;
+mod safety;
 mod sensors;
 mod server;
+mod simulation;
 mod state;
+mod storage;
+#[cfg(test)]
+mod testing;
+mod timeseries;
 
-fn load_config() -> config::Configuration {
-    let config = Configuration::from_file(std::path::Path::new("./growpi.toml"));
-    match config {
+fn load_config(path: &Path) -> config::Configuration {
+    match Configuration::load_or_create(path) {
         Ok(config) => config,
-        Err(_) => {
-            let config = Configuration::default();
-            config
-                .save_to_file(std::path::Path::new("./growpi.toml"))
-                .expect("Could not create default config in ./growpi.toml");
-            config
+        Err(e) => {
+            // Refuse to start instead of running with settings nobody asked for
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn check_config(path: &Path) {
+    let result = Configuration::from_file(path)
+        .map_err(|e| e.context(format!("Could not read {}", path.display())))
+        .and_then(|config| Ok(config.validate()?));
+    match result {
+        Ok(_) => println!("{} is valid", path.display()),
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
         }
     }
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
-    let config = load_config();
-    let program_state = init_state(config).unwrap();
+    let args = Args::parse();
+    let paths = args.paths();
+    let command = args.command.unwrap_or(Command::Serve);
+
+    match command {
+        Command::CheckConfig => return check_config(&paths.config),
+        Command::Export { data, zone, format } => {
+            let config = load_config(&paths.config);
+            let output = std::io::stdout().lock();
+            let zones = config
+                .resolve_zones()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>();
+            let result = storage::open(&config.storage_settings, &paths, &zones)
+                .and_then(|storage| export::export(storage.as_ref(), &zone, data, format, output));
+            if let Err(e) = result {
+                eprintln!("{:#}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        _ => (),
+    }
+
+    let config = load_config(&paths.config);
+    let clock = clock::from_config(&config);
+    let program_state = match init_state(config, clock, paths) {
+        Ok(program_state) => program_state,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let (relay_safety_handle, saved_relay_states) = {
+        let program_state = program_state.lock().await;
+        (
+            program_state.relay.safety_handle(),
+            program_state.paths.relay_states(),
+        )
+    };
+    safety::install_panic_hook(relay_safety_handle.clone(), program_state.clone());
+    tokio::spawn(safety::shutdown_on_signal(
+        relay_safety_handle,
+        program_state.clone(),
+        saved_relay_states,
+    ));
+
+    if let Command::Calibrate { samples } = command {
+        if let Err(e) = calibrate::run_calibration(program_state, samples).await {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    tokio::spawn(config::watch_config_file(program_state.clone()));
 
     let program_state_clone = program_state.clone();
     let control_thread_handle =
         tokio::spawn(async move { control::control_thread(program_state_clone).await });
 
-    let args = std::env::args().collect::<Vec<_>>();
-
-    let mode = args.get(1).map(|x| x.as_str());
-
-    match mode {
-        Some("cli") => run_cli(program_state.clone()).await,
-        _ => run_server(program_state.clone()).await,
+    match command {
+        Command::Cli => run_cli(program_state.clone()).await,
+        _ => run_server(program_state.clone(), args.listen).await,
     }
 
     let _ = control_thread_handle.await;
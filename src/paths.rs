@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+
+use crate::{config, control::data_logging::Bucket};
+
+/// Where the controller reads its configuration and keeps its data files
+#[derive(Clone)]
+pub struct Paths {
+    pub config: PathBuf,
+    pub data_dir: PathBuf,
+}
+
+impl Paths {
+    pub fn history(&self, zone: &str) -> PathBuf {
+        self.zone_file(zone, "history.csv")
+    }
+
+    pub fn datalog(&self, zone: &str) -> PathBuf {
+        self.zone_file(zone, "datalog.csv")
+    }
+
+    /// Temperature and soil moisture summarised per hour or day
+    pub fn datalog_rollups(&self, zone: &str, bucket: Bucket) -> PathBuf {
+        self.zone_file(zone, &format!("datalog.{}.csv", bucket.name()))
+    }
+
+    /// Readings of every sensor, one row per sensor
+    pub fn sensor_log(&self, zone: &str) -> PathBuf {
+        self.zone_file(zone, "sensors.csv")
+    }
+
+    /// When each camera image was taken
+    pub fn image_log(&self) -> PathBuf {
+        self.data_dir.join("growpi.images.csv")
+    }
+
+    /// Database of every zone, used instead of the CSV files with the SQLite storage backend
+    #[cfg(feature = "sqlite")]
+    pub fn database(&self) -> PathBuf {
+        self.data_dir.join("growpi.db")
+    }
+
+    /// Every relay switching, see [`crate::actuators::RelayEvent`]
+    pub fn relay_events(&self) -> PathBuf {
+        self.data_dir.join("growpi.relay_events.csv")
+    }
+
+    /// Relay states kept for restoring them after an unexpected restart
+    pub fn relay_states(&self) -> PathBuf {
+        self.data_dir.join("growpi.relays.toml")
+    }
+
+    /// Where camera images are kept, one file per image
+    pub fn images_dir(&self) -> PathBuf {
+        self.data_dir.join("growpi.images")
+    }
+
+    /// The camera image taken at `timestamp`
+    pub fn image(&self, timestamp: i64) -> PathBuf {
+        self.images_dir().join(format!("{}.jpeg", timestamp))
+    }
+
+    pub fn grow_stage(&self, zone: &str) -> PathBuf {
+        self.zone_file(zone, "grow_stage.toml")
+    }
+
+    /// The default zone keeps the file names from before there were zones
+    fn zone_file(&self, zone: &str, name: &str) -> PathBuf {
+        match zone == config::DEFAULT_ZONE {
+            true => self.data_dir.join(format!("growpi.{}", name)),
+            false => self.data_dir.join(format!("growpi.{}.{}", zone, name)),
+        }
+    }
+}
+
+impl Default for Paths {
+    fn default() -> Self {
+        Paths {
+            config: PathBuf::from(config::FILE_PATH),
+            data_dir: PathBuf::from("."),
+        }
+    }
+}
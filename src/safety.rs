@@ -0,0 +1,144 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::bail;
+use chrono::{DateTime, TimeDelta, Utc};
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::{
+    actuators::{self, RequestSource},
+    config::WaterPumpSettings,
+    history::WateringRecord,
+    io::RelaySafetyHandle,
+    state::ProgramStateShared,
+};
+
+pub fn log_intervention(message: &str) {
+    eprintln!("Safety: {}", message);
+}
+
+/// Records the relays on `pins` as switched off, unless the program state is in use
+///
+/// Waiting for the program state could hang the panic hook or the shutdown.
+fn try_record_switched_off(program_state: &ProgramStateShared, pins: &[u8], reason: &str) {
+    match program_state.try_lock() {
+        Ok(mut program_state) => {
+            actuators::record_switched_off(&mut program_state, pins, RequestSource::Safety, reason)
+        }
+        Err(_) => {
+            log_intervention("Could not record the relay events, the program state is in use")
+        }
+    }
+}
+
+/// Switches all relays off before the default panic handling runs
+pub fn install_panic_hook(relay: RelaySafetyHandle, program_state: ProgramStateShared) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        match relay.all_off() {
+            Ok(on_pins) => {
+                log_intervention("Panicked, switched all relays off");
+                try_record_switched_off(&program_state, &on_pins, "Panicked");
+            }
+            Err(e) => log_intervention(&format!("Panicked, could not switch relays off: {}", e)),
+        }
+        default_hook(info);
+    }));
+}
+
+/// Switches all relays off and exits once the process is asked to terminate
+///
+/// Removes the saved relay states once the relays are off, so they are not restored as if the
+/// controller had stopped unexpectedly.
+pub async fn shutdown_on_signal(
+    relay: RelaySafetyHandle,
+    program_state: ProgramStateShared,
+    saved_relay_states: PathBuf,
+) -> anyhow::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let signal_name = tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = interrupt.recv() => "SIGINT",
+    };
+    match relay.all_off() {
+        Ok(on_pins) => {
+            let _ = std::fs::remove_file(saved_relay_states);
+            log_intervention(&format!(
+                "Received {}, switched all relays off",
+                signal_name
+            ));
+            try_record_switched_off(
+                &program_state,
+                &on_pins,
+                &format!("Received {}", signal_name),
+            );
+        }
+        Err(e) => log_intervention(&format!(
+            "Received {}, could not switch relays off: {}",
+            signal_name, e
+        )),
+    }
+    std::process::exit(0);
+}
+
+/// Fails if running the pump for `duration` would exceed the configured limits
+pub fn check_pump_request(
+    settings: &WaterPumpSettings,
+    watering_records: &[WateringRecord],
+    now: DateTime<Utc>,
+    duration: Duration,
+    water_mass_g: u64,
+) -> anyhow::Result<()> {
+    if duration > Duration::from_secs(settings.max_run_secs) {
+        bail!(
+            "Refused to run the pump for {:.1}s, the maximum is {}s",
+            duration.as_secs_f32(),
+            settings.max_run_secs
+        );
+    }
+    let hour_ago = (now - TimeDelta::hours(1)).timestamp();
+    let pumped_last_hour: u64 = watering_records
+        .iter()
+        .filter(|record| record.time > hour_ago)
+        .map(|record| record.amount)
+        .sum();
+    if pumped_last_hour + water_mass_g > settings.max_grams_per_hour {
+        bail!(
+            "Refused to pump {}g, {}g were already pumped in the last hour and the limit is {}g",
+            water_mass_g,
+            pumped_last_hour,
+            settings.max_grams_per_hour
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::WateringReason;
+
+    #[test]
+    fn test_check_pump_request() {
+        let settings = WaterPumpSettings {
+            grams_per_millisecond: 0.05,
+            max_run_secs: 10,
+            max_grams_per_hour: 300,
+        };
+        let now = Utc::now();
+        let record = |minutes_ago, amount| {
+            WateringRecord::new(
+                now - TimeDelta::minutes(minutes_ago),
+                amount,
+                0.3,
+                WateringReason::Manual,
+            )
+        };
+        let seconds = Duration::from_secs;
+
+        assert!(check_pump_request(&settings, &[], now, seconds(4), 200).is_ok());
+        assert!(check_pump_request(&settings, &[], now, seconds(11), 550).is_err());
+        assert!(check_pump_request(&settings, &[record(30, 200)], now, seconds(4), 200).is_err());
+        assert!(check_pump_request(&settings, &[record(70, 200)], now, seconds(4), 200).is_ok());
+    }
+}
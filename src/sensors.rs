@@ -1,32 +1,218 @@
-use crate::{config::*, io::get_input_voltage};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
 
-pub fn get_temperature(config: &Configuration) -> anyhow::Result<f32> {
-    let voltage = get_input_voltage(config.thermistor_settings.pin)?;
+use crate::{config::*, state::ProgramState};
 
-    let k = config.board_settings.logic_level / voltage - 1.;
+/// A converted sensor value, as shown by the API and the CLI and written to the data log
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SensorReading {
+    pub name: String,
+    pub value: f32,
+    pub unit: String,
+}
+
+pub fn get_temperature(program_state: &mut ProgramState, zone: usize) -> anyhow::Result<f32> {
+    let config = &program_state.zones[zone].config;
+    let voltage = program_state
+        .analog
+        .read_voltage(config.thermistor_settings.pin)?;
+    Ok(voltage_to_temperature(config, voltage))
+}
+
+pub fn get_soil_moisture(program_state: &mut ProgramState, zone: usize) -> anyhow::Result<f32> {
+    let config = &program_state.zones[zone].config;
+    let voltage = program_state
+        .analog
+        .read_voltage(config.soil_moisture_settings.pin)?;
+    Ok(voltage_to_soil_moisture(config, voltage))
+}
+
+/// The thermistor and soil moisture sensor followed by the sensors configured for the zone
+pub fn zone_sensors(config: &Configuration) -> Vec<SensorSettings> {
+    let built_in = [
+        SensorSettings {
+            name: TEMPERATURE_SENSOR.to_string(),
+            pin: config.thermistor_settings.pin,
+            unit: "C".to_string(),
+            zone: None,
+            kind: config.thermistor_settings.kind(),
+        },
+        SensorSettings {
+            name: SOIL_MOISTURE_SENSOR.to_string(),
+            pin: config.soil_moisture_settings.pin,
+            unit: String::new(),
+            zone: None,
+            kind: config.soil_moisture_settings.kind(),
+        },
+    ];
+    built_in
+        .into_iter()
+        .chain(config.sensors.iter().cloned())
+        .collect()
+}
+
+/// Reads every sensor of a zone
+pub fn read_sensors(
+    program_state: &mut ProgramState,
+    zone: usize,
+) -> anyhow::Result<Vec<SensorReading>> {
+    let config = &program_state.zones[zone].config;
+    zone_sensors(config)
+        .into_iter()
+        .map(|sensor| {
+            let voltage = program_state.analog.read_voltage(sensor.pin)?;
+            Ok(SensorReading {
+                value: convert(&sensor.kind, config.board_settings.logic_level, voltage),
+                name: sensor.name,
+                unit: sensor.unit,
+            })
+        })
+        .collect()
+}
+
+/// Reads the sensor of a zone called `name`
+pub fn read_sensor(
+    program_state: &mut ProgramState,
+    zone: usize,
+    name: &str,
+) -> anyhow::Result<f32> {
+    let config = &program_state.zones[zone].config;
+    let sensor = zone_sensors(config)
+        .into_iter()
+        .find(|sensor| sensor.name == name)
+        .with_context(|| format!("No sensor called {}", name))?;
+    let voltage = program_state.analog.read_voltage(sensor.pin)?;
+    Ok(convert(
+        &sensor.kind,
+        config.board_settings.logic_level,
+        voltage,
+    ))
+}
+
+/// Turns the voltage read on a sensor's channel into its value
+pub fn convert(kind: &SensorKind, logic_level: f32, voltage: f32) -> f32 {
+    match kind {
+        SensorKind::Thermistor {
+            voltage_divider_resistance,
+            nominal_resistance,
+            nominal_temperature,
+            thermal_constant,
+            resistor,
+        } => {
+            let k = logic_level / voltage - 1.;
+            let k = match resistor {
+                VoltageDividerResistor::R1 => k,
+                VoltageDividerResistor::R2 => 1. / k,
+            };
+            let resistance = k * voltage_divider_resistance;
+
+            1. / ((1. / nominal_temperature)
+                + (1. / thermal_constant * f32::ln(resistance / nominal_resistance)))
+                - 273.15
+        }
+        SensorKind::SoilMoisture {
+            voltage_100,
+            voltage_nominal,
+            moisture_nominal,
+        } => {
+            let voltage_zero_humidity =
+                voltage_zero_humidity(*voltage_100, *voltage_nominal, *moisture_nominal);
+            (voltage - voltage_zero_humidity) / (voltage_100 - voltage_zero_humidity)
+        }
+        SensorKind::Voltage => voltage,
+        SensorKind::Linear { scale, offset } => voltage * scale + offset,
+        SensorKind::Curve { points } => match points.iter().position(|point| voltage < point[0]) {
+            Some(0) => points[0][1],
+            Some(index) => {
+                let [voltage_0, value_0] = points[index - 1];
+                let [voltage_1, value_1] = points[index];
+                value_0 + (voltage - voltage_0) / (voltage_1 - voltage_0) * (value_1 - value_0)
+            }
+            None => points.last().map_or(f32::NAN, |point| point[1]),
+        },
+    }
+}
+
+pub fn voltage_to_temperature(config: &Configuration, voltage: f32) -> f32 {
+    convert(
+        &config.thermistor_settings.kind(),
+        config.board_settings.logic_level,
+        voltage,
+    )
+}
+
+/// Inverse of [`voltage_to_temperature`], used to feed simulated readings through the calibration
+pub fn temperature_to_voltage(config: &Configuration, temperature: f32) -> f32 {
+    let temperature = temperature + 273.15;
+    let resistance = config.thermistor_settings.nominal_resistance
+        * f32::exp(
+            config.thermistor_settings.thermal_constant
+                * (1. / temperature - 1. / config.thermistor_settings.nominal_temperature),
+        );
+    let k = resistance / config.thermistor_settings.voltage_divider_resistance;
     let k = match config.thermistor_settings.resistor {
         VoltageDividerResistor::R1 => k,
         VoltageDividerResistor::R2 => 1. / k,
     };
-    let resistance = k * config.thermistor_settings.voltage_divider_resistance;
+    config.board_settings.logic_level / (k + 1.)
+}
 
-    let temperature = 1.
-        / ((1. / config.thermistor_settings.nominal_temperature)
-            + (1. / config.thermistor_settings.thermal_constant
-                * f32::ln(resistance / config.thermistor_settings.nominal_resistance)))
-        - 273.15;
-    Ok(temperature)
+fn voltage_zero_humidity(voltage_100: f32, voltage_nominal: f32, moisture_nominal: f32) -> f32 {
+    (voltage_nominal - voltage_100 * moisture_nominal) / (1. - moisture_nominal)
 }
 
-pub fn get_soil_moisture(config: &Configuration) -> anyhow::Result<f32> {
-    let voltage = get_input_voltage(config.soil_moisture_settings.pin)?;
+pub fn voltage_to_soil_moisture(config: &Configuration, voltage: f32) -> f32 {
+    convert(
+        &config.soil_moisture_settings.kind(),
+        config.board_settings.logic_level,
+        voltage,
+    )
+}
+
+/// Inverse of [`voltage_to_soil_moisture`], used to feed simulated readings through the calibration
+pub fn soil_moisture_to_voltage(config: &Configuration, moisture: f32) -> f32 {
+    let settings = &config.soil_moisture_settings;
+    let voltage_zero_humidity = voltage_zero_humidity(
+        settings.voltage_100,
+        settings.voltage_nominal,
+        settings.moisture_nominal,
+    );
+    voltage_zero_humidity + moisture * (settings.voltage_100 - voltage_zero_humidity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calibration_round_trip() {
+        let mut config = Configuration::default();
+        for resistor in [VoltageDividerResistor::R1, VoltageDividerResistor::R2] {
+            config.thermistor_settings.resistor = resistor;
+            for temperature in [5., 25., 31.5, 40.] {
+                let voltage = temperature_to_voltage(&config, temperature);
+                assert!((voltage_to_temperature(&config, voltage) - temperature).abs() < 0.01);
+            }
+        }
+        for moisture in [0., 0.41, 0.75, 1.] {
+            let voltage = soil_moisture_to_voltage(&config, moisture);
+            assert!((voltage_to_soil_moisture(&config, voltage) - moisture).abs() < 0.001);
+        }
+    }
 
-    let voltage_zero_humidity: f32 = (config.soil_moisture_settings.voltage_nominal
-        - config.soil_moisture_settings.voltage_100
-            * config.soil_moisture_settings.moisture_nominal)
-        / (1. - config.soil_moisture_settings.moisture_nominal);
+    #[test]
+    fn test_convert() {
+        let linear = SensorKind::Linear {
+            scale: 100. / 3.3,
+            offset: -10.,
+        };
+        assert!((convert(&linear, 3.3, 1.65) - 40.).abs() < 0.001);
 
-    let humidity = (voltage - voltage_zero_humidity)
-        / (config.soil_moisture_settings.voltage_100 - voltage_zero_humidity);
-    Ok(humidity)
+        let curve = SensorKind::Curve {
+            points: vec![[0.5, 0.], [1.5, 50.], [2.5, 60.]],
+        };
+        for (voltage, value) in [(0., 0.), (1., 25.), (1.5, 50.), (2., 55.), (3.3, 60.)] {
+            assert!((convert(&curve, 3.3, voltage) - value).abs() < 0.001);
+        }
+    }
 }
@@ -1,40 +1,70 @@
-use std::error::Error;
+use std::{error::Error, net::SocketAddr};
 
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::{header, HeaderValue, Method, StatusCode},
     response::{IntoResponse, Response},
     routing::get,
     Json, Router,
 };
+use chrono::TimeDelta;
 use serde::{Deserialize, Serialize};
 use tower_http::cors::{Any, CorsLayer};
 
-use crate::{actuators, control, io::RelaySwitchState, sensors, state::ProgramStateShared};
+use crate::{
+    actuators::{
+        self, ActiveOverride, DeviceState, PumpProgress, RelayEvent, RelayOwner, RequestSource,
+    },
+    config::Configuration,
+    control::{
+        self,
+        data_logging::{self, Bucket},
+        soil::NextWatering,
+    },
+    grow::{self, StageProgress},
+    history::WateringReason,
+    io::RelaySwitchState,
+    sensors::{self, SensorReading},
+    state::{self, ProgramState, ProgramStateShared},
+};
 
-pub async fn run_server(program_state: ProgramStateShared) {
+pub async fn run_server(program_state: ProgramStateShared, listen: Option<SocketAddr>) {
     let app: Router = setup_router(program_state.clone());
     let port = program_state.lock().await.config.server_settings.port;
-    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port))
-        .await
-        .unwrap();
+    let listen = listen.unwrap_or(SocketAddr::from(([0, 0, 0, 0], port)));
+    let listener = tokio::net::TcpListener::bind(listen).await.unwrap();
     axum::serve(listener, app).await.unwrap();
 }
 
 fn setup_router(program_state: ProgramStateShared) -> Router {
     let cors = CorsLayer::new()
-        .allow_methods([Method::GET, Method::POST])
+        .allow_methods([Method::GET, Method::POST, Method::PUT])
+        .allow_headers(Any)
         .allow_origin(Any);
 
     Router::new()
         .route("/api/info", get(info_handler))
+        .route("/api/zones", get(zones_handler))
         .route("/api/switch/:device/:state", get(switch_handler))
+        .route("/api/config", get(config_handler))
+        .route(
+            "/api/config/:section",
+            get(config_section_handler).put(config_section_update_handler),
+        )
+        .route("/api/override/:device/clear", get(override_clear_handler))
+        .route("/api/override/:device/:state", get(override_handler))
+        .route("/api/pump/stop", get(pump_stop_handler))
         .route("/api/pump/:quantity", get(pump_handler))
         .route("/api/refresh_image", get(image_refresh_handler))
         .route(
             "/api/watering_history/:entries",
             get(watering_history_handler),
         )
+        .route("/api/datalog", get(datalog_handler))
+        .route("/api/relay_events", get(relay_events_handler))
+        .route("/api/next_watering", get(next_watering_handler))
+        .route("/api/grow_stage", get(grow_stage_handler))
+        .route("/api/grow_stage/advance", get(grow_stage_advance_handler))
         .route("/api/graceful_shutdown", get(graceful_shutdown_handler))
         .route("/image", get(image_handler))
         .route("/*path", get(site_handler))
@@ -43,13 +73,42 @@ fn setup_router(program_state: ProgramStateShared) -> Router {
         .layer(cors)
 }
 
+/// Selects a zone with `?zone=`, the first zone when left out
+#[derive(Deserialize)]
+struct ZoneQuery {
+    zone: Option<String>,
+}
+
+impl ZoneQuery {
+    fn index(&self, program_state: &ProgramState) -> Result<usize, StatusCode> {
+        program_state
+            .zone_index(self.zone.as_deref())
+            .map_err(|_| StatusCode::NOT_FOUND)
+    }
+}
+
+async fn zones_handler(State(program_state): State<ProgramStateShared>) -> Json<Vec<String>> {
+    let program_state = program_state.lock().await;
+    Json(
+        program_state
+            .zones
+            .iter()
+            .map(|zone| zone.name.clone())
+            .collect(),
+    )
+}
+
 async fn pump_handler(
     Path(quantity): Path<u16>,
+    Query(query): Query<ZoneQuery>,
     State(program_state): State<ProgramStateShared>,
 ) -> impl IntoResponse {
+    let zone = match query.index(&*program_state.lock().await) {
+        Ok(zone) => zone,
+        Err(status) => return status,
+    };
     let exec = async {
-        let mut program_state = program_state.lock().await;
-        actuators::pump_water(quantity, &mut program_state)?;
+        actuators::pump_water(zone, quantity, WateringReason::Manual, &program_state).await?;
         Ok::<_, Box<dyn Error>>(())
     };
     match exec.await {
@@ -58,51 +117,191 @@ async fn pump_handler(
     }
 }
 
+async fn pump_stop_handler(
+    Query(query): Query<ZoneQuery>,
+    State(program_state): State<ProgramStateShared>,
+) -> impl IntoResponse {
+    let mut program_state = program_state.lock().await;
+    let zone = match query.index(&program_state) {
+        Ok(zone) => zone,
+        Err(status) => return status,
+    };
+    match actuators::stop_pump(&mut program_state, zone) {
+        Ok(_) => StatusCode::OK,
+        Err(_) => StatusCode::CONFLICT,
+    }
+}
+
 async fn switch_handler(
     Path((device, state)): Path<(String, RelaySwitchState)>,
+    Query(query): Query<ZoneQuery>,
     State(program_state): State<ProgramStateShared>,
 ) -> impl IntoResponse {
-    let exec = async {
-        let mut program_state = program_state.lock().await;
-        match device.as_str() {
-            "lights" => actuators::switch_lights(state, &mut program_state)?,
-            "fan" => actuators::switch_fan(state, &mut program_state)?,
-            _ => (),
-        }
-        Ok::<_, Box<dyn Error>>(())
+    let mut program_state = program_state.lock().await;
+    let zone = match query.index(&program_state) {
+        Ok(zone) => zone,
+        Err(status) => return status,
     };
-    match exec.await {
+    if actuators::find_device(&program_state, zone, &device).is_none() {
+        return StatusCode::NOT_FOUND;
+    }
+    let request = actuators::manual_request(state, RequestSource::Http, &program_state);
+    match actuators::switch_device(zone, &device, request, &mut program_state) {
         Ok(_) => StatusCode::OK,
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        Err(_) => StatusCode::BAD_REQUEST,
+    }
+}
+
+async fn config_handler(State(program_state): State<ProgramStateShared>) -> Json<Configuration> {
+    Json(program_state.lock().await.config.clone())
+}
+
+async fn config_section_handler(
+    Path(section): Path<String>,
+    State(program_state): State<ProgramStateShared>,
+) -> Response {
+    match program_state.lock().await.config.get_section(&section) {
+        Ok(section) => Json(section).into_response(),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Replaces a settings section, applies it to the running controller and saves it
+async fn config_section_update_handler(
+    Path(section): Path<String>,
+    State(program_state): State<ProgramStateShared>,
+    Json(value): Json<toml::Value>,
+) -> Response {
+    let mut program_state = program_state.lock().await;
+    if program_state.config.get_section(&section).is_err() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let config = match program_state.config.with_section(&section, value) {
+        Ok(config) => config,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    if let Err(e) = state::apply_config(&mut program_state, config.clone()) {
+        return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+    match config.save_to_file(&program_state.paths.config) {
+        Ok(_) => StatusCode::OK.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct OverrideQuery {
+    minutes: Option<i64>,
+    zone: Option<String>,
+}
+
+/// Holds a device in a state, for `?minutes=` or until the override is cleared
+async fn override_handler(
+    Path((device, state)): Path<(String, RelaySwitchState)>,
+    Query(query): Query<OverrideQuery>,
+    State(program_state): State<ProgramStateShared>,
+) -> impl IntoResponse {
+    let mut program_state = program_state.lock().await;
+    let Ok(zone) = program_state.zone_index(query.zone.as_deref()) else {
+        return StatusCode::NOT_FOUND;
+    };
+    if actuators::find_device(&program_state, zone, &device).is_none() {
+        return StatusCode::NOT_FOUND;
+    }
+    let Ok(duration) = query.minutes.map(actuators::override_duration).transpose() else {
+        return StatusCode::BAD_REQUEST;
+    };
+    match actuators::set_override(
+        zone,
+        &device,
+        state,
+        duration,
+        RequestSource::Http,
+        &mut program_state,
+    ) {
+        Ok(_) => StatusCode::OK,
+        Err(_) => StatusCode::BAD_REQUEST,
+    }
+}
+
+async fn override_clear_handler(
+    Path(device): Path<String>,
+    Query(query): Query<ZoneQuery>,
+    State(program_state): State<ProgramStateShared>,
+) -> impl IntoResponse {
+    let mut program_state = program_state.lock().await;
+    let zone = match query.index(&program_state) {
+        Ok(zone) => zone,
+        Err(status) => return status,
+    };
+    if actuators::find_device(&program_state, zone, &device).is_none() {
+        return StatusCode::NOT_FOUND;
+    }
+    match actuators::clear_override(zone, &device, &mut program_state) {
+        Ok(_) => StatusCode::OK,
+        Err(_) => StatusCode::BAD_REQUEST,
     }
 }
 
 #[derive(Serialize, Deserialize)]
 struct Info {
+    zone: String,
     temperature: f32,
     soil_moisture: f32,
     fan_state: RelaySwitchState,
     light_state: RelaySwitchState,
     pump_state: RelaySwitchState,
+    heater_state: Option<RelaySwitchState>,
+    light_brightness: Option<f32>,
+    pump_progress: Option<PumpProgress>,
+    relay_owners: Vec<RelayOwner>,
+    overrides: Vec<ActiveOverride>,
+    devices: Vec<DeviceState>,
+    sensors: Vec<SensorReading>,
 }
 
 async fn info_handler(
+    Query(query): Query<ZoneQuery>,
     State(program_state): State<ProgramStateShared>,
-) -> Result<Json<Info>, String> {
+) -> Result<Json<Info>, Response> {
     let mut program_state = program_state.lock().await;
-    let temperature = sensors::get_temperature(&program_state.config).map_err(|e| e.to_string())?;
-    let soil_moisture =
-        sensors::get_soil_moisture(&program_state.config).map_err(|e| e.to_string())?;
-    let fan_state = actuators::get_fan_state(&mut program_state).map_err(|e| e.to_string())?;
-    let light_state = actuators::get_light_state(&mut program_state).map_err(|e| e.to_string())?;
-    let pump_state =
-        actuators::get_water_pump_state(&mut program_state).map_err(|e| e.to_string())?;
+    let zone = query
+        .index(&program_state)
+        .map_err(IntoResponse::into_response)?;
+    let error = |e: anyhow::Error| e.to_string().into_response();
+    let temperature = sensors::get_temperature(&mut program_state, zone).map_err(error)?;
+    let soil_moisture = sensors::get_soil_moisture(&mut program_state, zone).map_err(error)?;
+    let fan_state = actuators::get_fan_state(&mut program_state, zone).map_err(error)?;
+    let light_state = actuators::get_light_state(&mut program_state, zone).map_err(error)?;
+    let pump_state = actuators::get_water_pump_state(&mut program_state, zone).map_err(error)?;
+    let heater_state = match program_state.zones[zone].config.relay_settings.heater_pin {
+        Some(_) => Some(actuators::get_heater_state(&mut program_state, zone).map_err(error)?),
+        None => None,
+    };
+    let light_brightness = program_state
+        .dimmer
+        .as_ref()
+        .filter(|_| zone == 0)
+        .map(|dimmer| dimmer.brightness());
+    let pump_progress = actuators::get_pump_progress(&program_state, zone);
+    let relay_owners = actuators::get_relay_owners(&mut program_state).map_err(error)?;
+    let overrides = actuators::get_overrides(&program_state, zone);
+    let devices = actuators::get_device_states(&mut program_state, zone).map_err(error)?;
+    let sensors = sensors::read_sensors(&mut program_state, zone).map_err(error)?;
     Ok(Json(Info {
+        zone: program_state.zones[zone].name.clone(),
         temperature,
         soil_moisture,
         fan_state,
         light_state,
         pump_state,
+        heater_state,
+        light_brightness,
+        pump_progress,
+        relay_owners,
+        overrides,
+        devices,
+        sensors,
     }))
 }
 
@@ -141,8 +340,9 @@ fn serve_site(path: Option<String>) -> Response {
     response
 }
 
-async fn image_handler() -> Response {
-    let bytes = std::fs::read(control::imaging::get_image_path());
+async fn image_handler(State(program_state): State<ProgramStateShared>) -> Response {
+    let path = control::imaging::latest_image(&*program_state.lock().await);
+    let bytes = path.and_then(|path| Ok(std::fs::read(path)?));
     let response = bytes.map(|bytes| {
         let mut r = bytes.into_response();
         r.headers_mut()
@@ -158,11 +358,15 @@ async fn image_refresh_handler(State(program_state): State<ProgramStateShared>)
 
 async fn watering_history_handler(
     Path(entries): Path<usize>,
+    Query(query): Query<ZoneQuery>,
     State(program_state): State<ProgramStateShared>,
 ) -> Response {
-    let records = program_state
-        .lock()
-        .await
+    let program_state = program_state.lock().await;
+    let zone = match query.index(&program_state) {
+        Ok(zone) => zone,
+        Err(status) => return status.into_response(),
+    };
+    let records = program_state.zones[zone]
         .history
         .watering_records
         .iter()
@@ -178,6 +382,105 @@ async fn watering_history_handler(
     }
 }
 
+/// A time range as unix timestamps, `to` defaults to now and `from` to a day before `to`
+#[derive(Deserialize)]
+struct TimeRangeQuery {
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+impl TimeRangeQuery {
+    fn range(&self, program_state: &ProgramState) -> Result<(i64, i64), StatusCode> {
+        let to = self
+            .to
+            .unwrap_or_else(|| program_state.clock.now().timestamp());
+        let from = self.from.unwrap_or(to - TimeDelta::days(1).num_seconds());
+        match from <= to {
+            true => Ok((from, to)),
+            false => Err(StatusCode::BAD_REQUEST),
+        }
+    }
+}
+
+/// Samples are summarised per `bucket` if one is given
+#[derive(Deserialize)]
+struct BucketQuery {
+    bucket: Option<Bucket>,
+}
+
+async fn datalog_handler(
+    Query(zone_query): Query<ZoneQuery>,
+    Query(time_range): Query<TimeRangeQuery>,
+    Query(query): Query<BucketQuery>,
+    State(program_state): State<ProgramStateShared>,
+) -> Response {
+    let program_state = program_state.lock().await;
+    let zone = match zone_query.index(&program_state) {
+        Ok(zone) => zone,
+        Err(status) => return status.into_response(),
+    };
+    let (from, to) = match time_range.range(&program_state) {
+        Ok(range) => range,
+        Err(status) => return status.into_response(),
+    };
+    let storage = program_state.storage.as_ref();
+    let zone_name = &program_state.zones[zone].name;
+    let response = match query.bucket {
+        Some(bucket) => data_logging::summarise(storage, zone_name, from, to, bucket)
+            .map(|buckets| Json(buckets).into_response()),
+        None => storage
+            .data_records_between(zone_name, from, to)
+            .map(|records| Json(records).into_response()),
+    };
+    response.unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
+
+async fn relay_events_handler(
+    Query(zone_query): Query<ZoneQuery>,
+    Query(time_range): Query<TimeRangeQuery>,
+    State(program_state): State<ProgramStateShared>,
+) -> Result<Json<Vec<RelayEvent>>, StatusCode> {
+    let program_state = program_state.lock().await;
+    let zone = zone_query.index(&program_state)?;
+    let (from, to) = time_range.range(&program_state)?;
+    actuators::get_relay_events(&program_state, zone, from, to)
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+async fn next_watering_handler(
+    Query(query): Query<ZoneQuery>,
+    State(program_state): State<ProgramStateShared>,
+) -> Result<Json<Option<NextWatering>>, StatusCode> {
+    let program_state = program_state.lock().await;
+    let zone = query.index(&program_state)?;
+    Ok(Json(control::soil::get_next_watering(&program_state, zone)))
+}
+
+async fn grow_stage_handler(
+    Query(query): Query<ZoneQuery>,
+    State(program_state): State<ProgramStateShared>,
+) -> Result<Json<Option<StageProgress>>, StatusCode> {
+    let program_state = program_state.lock().await;
+    let zone = query.index(&program_state)?;
+    Ok(Json(grow::get_stage_progress(&program_state, zone)))
+}
+
+async fn grow_stage_advance_handler(
+    Query(query): Query<ZoneQuery>,
+    State(program_state): State<ProgramStateShared>,
+) -> impl IntoResponse {
+    let mut program_state = program_state.lock().await;
+    let zone = match query.index(&program_state) {
+        Ok(zone) => zone,
+        Err(status) => return status,
+    };
+    match grow::advance_stage(&mut program_state, zone) {
+        Ok(_) => StatusCode::OK,
+        Err(_) => StatusCode::CONFLICT,
+    }
+}
+
 async fn graceful_shutdown_handler() -> Response {
     match system_shutdown::shutdown() {
         Ok(_) => StatusCode::OK.into_response(),
@@ -0,0 +1,158 @@
+use std::f32::consts::PI;
+
+use chrono::{DateTime, Local, TimeDelta, Timelike, Utc};
+
+use crate::{clock::SharedClock, config::Configuration, io::RelaySwitchState, sensors};
+
+/// Longest time step the model is integrated over at once, in simulated seconds
+const MAX_STEP_SECS: f32 = 60.;
+
+/// A very simple model of the grow bucket, driven by the simulated relays and
+/// read back through the simulated analog inputs.
+pub struct GrowBucket {
+    config: Configuration,
+    clock: SharedClock,
+    last_update: DateTime<Utc>,
+    temperature: f32,
+    soil_moisture: f32,
+    light_on: bool,
+    fan_on: bool,
+    heater_on: bool,
+    pump_on: bool,
+}
+
+impl GrowBucket {
+    pub fn new(config: &Configuration, clock: SharedClock) -> GrowBucket {
+        let now = clock.now();
+        let mut bucket = GrowBucket {
+            config: config.clone(),
+            clock,
+            last_update: now,
+            temperature: 0.,
+            soil_moisture: config.simulation_settings.initial_soil_moisture,
+            light_on: false,
+            fan_on: false,
+            heater_on: false,
+            pump_on: false,
+        };
+        bucket.temperature = bucket.ambient_temperature(now);
+        bucket
+    }
+
+    pub fn set_relay(&mut self, pin: u8, state: RelaySwitchState) {
+        self.update(self.clock.now());
+        let on = matches!(state, RelaySwitchState::On);
+        let relay_settings = &self.config.relay_settings;
+        if pin == relay_settings.light_pin {
+            self.light_on = on;
+        }
+        if pin == relay_settings.fan_pin {
+            self.fan_on = on;
+        }
+        if Some(pin) == relay_settings.heater_pin {
+            self.heater_on = on;
+        }
+        if pin == relay_settings.water_pump_pin {
+            self.pump_on = on;
+        }
+    }
+
+    /// Returns the voltage the sensor on `pin` would produce, or `None` if no sensor is modelled on it
+    pub fn read_voltage(&mut self, pin: u8) -> Option<f32> {
+        self.update(self.clock.now());
+        if pin == self.config.thermistor_settings.pin {
+            return Some(sensors::temperature_to_voltage(
+                &self.config,
+                self.temperature,
+            ));
+        }
+        if pin == self.config.soil_moisture_settings.pin {
+            return Some(sensors::soil_moisture_to_voltage(
+                &self.config,
+                self.soil_moisture,
+            ));
+        }
+        None
+    }
+
+    /// Ambient temperature following a day/night curve, warmest at 15:00 local time
+    fn ambient_temperature(&self, time: DateTime<Utc>) -> f32 {
+        let settings = &self.config.simulation_settings;
+        let time = time.with_timezone(&Local);
+        let hour = time.hour() as f32 + time.minute() as f32 / 60.;
+        let mean = (settings.ambient_temperature_day + settings.ambient_temperature_night) / 2.;
+        let amplitude =
+            (settings.ambient_temperature_day - settings.ambient_temperature_night) / 2.;
+        mean + amplitude * f32::cos(2. * PI * (hour - 15.) / 24.)
+    }
+
+    fn update(&mut self, now: DateTime<Utc>) {
+        while self.last_update < now {
+            let step_secs =
+                ((now - self.last_update).num_milliseconds() as f32 / 1000.).min(MAX_STEP_SECS);
+            let step_end = self.last_update + TimeDelta::milliseconds((step_secs * 1000.) as i64);
+            self.step(step_secs, step_end);
+            self.last_update = step_end;
+        }
+    }
+
+    fn step(&mut self, secs: f32, time: DateTime<Utc>) {
+        let settings = &self.config.simulation_settings;
+        let hours = secs / 3600.;
+
+        let mut heat_loss = settings.heat_loss_per_hour;
+        if self.fan_on {
+            heat_loss += settings.fan_heat_loss_per_hour;
+        }
+        let mut temperature_change =
+            heat_loss * (self.ambient_temperature(time) - self.temperature);
+        if self.light_on {
+            temperature_change += settings.light_heating_per_hour;
+        }
+        if self.heater_on {
+            temperature_change += settings.heater_heating_per_hour;
+        }
+        self.temperature += temperature_change * hours;
+
+        let mut moisture_change = -settings.soil_drainage_per_hour * self.soil_moisture * hours;
+        if self.pump_on {
+            let grams = self.config.water_pump_settings.grams_per_millisecond * secs * 1000.;
+            moisture_change += grams * settings.soil_moisture_per_gram;
+        }
+        self.soil_moisture = (self.soil_moisture + moisture_change).clamp(0., 1.);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::clock::SystemClock;
+
+    #[test]
+    fn test_grow_bucket_model() {
+        let config = Configuration::default();
+        let mut bucket = GrowBucket::new(&config, Arc::new(SystemClock));
+        let start = bucket.last_update;
+
+        let ambient = bucket.temperature;
+        bucket.light_on = true;
+        bucket.update(start + TimeDelta::hours(2));
+        let lit = bucket.temperature;
+        assert!(lit > ambient + 5.);
+
+        bucket.fan_on = true;
+        bucket.update(start + TimeDelta::hours(4));
+        assert!(bucket.temperature < lit);
+
+        let moisture = bucket.soil_moisture;
+        bucket.update(start + TimeDelta::hours(24));
+        assert!(bucket.soil_moisture < moisture);
+
+        let moisture = bucket.soil_moisture;
+        bucket.pump_on = true;
+        bucket.update(start + TimeDelta::hours(24) + TimeDelta::seconds(2));
+        assert!(bucket.soil_moisture > moisture + 0.1);
+    }
+}
@@ -1,22 +1,315 @@
 use std::sync::Arc;
 
-use tokio::sync::Mutex;
+use anyhow::{bail, Context};
+use tokio::sync::{Mutex, Notify};
 
-use crate::{config::Configuration, history::History, io};
+use crate::{
+    actuators::{self, Priority, PumpRun, RelayArbiter, RequestSource},
+    clock::SharedClock,
+    config::Configuration,
+    grow::GrowProgress,
+    history::History,
+    io,
+    paths::Paths,
+    storage::{self, Storage},
+};
 
 pub type ProgramStateShared = Arc<Mutex<ProgramState>>;
 pub struct ProgramState {
     pub config: Configuration,
     pub relay: io::Relay,
+    pub arbiter: RelayArbiter,
+    pub analog: Box<dyn io::AnalogInput>,
+    /// Dims the lights of the first zone
+    pub dimmer: Option<Box<dyn io::Dimmer>>,
+    pub camera: Arc<dyn io::Camera>,
+    pub clock: SharedClock,
+    pub zones: Vec<Zone>,
+    /// Where the zones' watering records and logged samples are kept
+    pub storage: Box<dyn Storage>,
+    /// Where configuration changes made at runtime are saved and data files are kept
+    pub paths: Paths,
+    /// Wakes the control loops after the configuration changed
+    pub config_changed: Arc<Notify>,
+}
+
+/// A grow bucket controlled on its own, see [`crate::config::ZoneSettings`]
+pub struct Zone {
+    pub name: String,
+    /// The configuration with the settings of this zone applied
+    pub config: Configuration,
     pub history: History,
+    pub pump_run: Option<PumpRun>,
+    pub grow_progress: Option<GrowProgress>,
+}
+
+impl ProgramState {
+    /// Index of the zone called `name`, the first zone if no name is given
+    pub fn zone_index(&self, name: Option<&str>) -> anyhow::Result<usize> {
+        match name {
+            None => Ok(0),
+            Some(name) => self
+                .zones
+                .iter()
+                .position(|zone| zone.name == name)
+                .with_context(|| format!("No zone called {}", name)),
+        }
+    }
 }
 
-pub fn init_state(config: Configuration) -> anyhow::Result<ProgramStateShared> {
-    let relay = io::Relay::new(&config)?;
-    let history = History::load().unwrap_or_default();
-    Ok(Arc::new(Mutex::new(ProgramState {
+pub fn init_state(
+    config: Configuration,
+    clock: SharedClock,
+    paths: Paths,
+) -> anyhow::Result<ProgramStateShared> {
+    let io::Io {
+        relay,
+        analog,
+        dimmer,
+        camera,
+    } = io::init_io(&config, clock.clone())?;
+    let zone_configs = config.resolve_zones();
+    let zone_names = zone_configs
+        .iter()
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+    let storage = storage::open(&config.storage_settings, &paths, &zone_names)?;
+    let zones = zone_configs
+        .into_iter()
+        .map(|(name, config)| {
+            // Watering as if the zone was never watered could drown the plant
+            let history = History::load(storage.as_ref(), &name)
+                .with_context(|| format!("Could not load the watering history of {}", name))?;
+            Ok(Zone {
+                history,
+                grow_progress: GrowProgress::load(&paths.grow_stage(&name)).ok(),
+                pump_run: None,
+                name,
+                config,
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+    let mut program_state = ProgramState {
         config,
         relay,
-        history,
-    })))
+        arbiter: RelayArbiter::default(),
+        analog,
+        dimmer,
+        camera,
+        clock,
+        zones,
+        storage,
+        paths,
+        config_changed: Arc::new(Notify::new()),
+    };
+    actuators::switch_all_off(&mut program_state, RequestSource::Startup, "Startup")?;
+    actuators::apply_startup_states(&mut program_state)?;
+    Ok(Arc::new(Mutex::new(program_state)))
+}
+
+/// Swaps in a new configuration, which the control loops pick up right away
+///
+/// The relays, sensors and dimmer are re-initialised when their settings changed.
+/// Returns the names of the settings sections that changed.
+pub fn apply_config(
+    program_state: &mut ProgramState,
+    config: Configuration,
+) -> anyhow::Result<Vec<String>> {
+    config.validate()?;
+    let changed_sections = program_state.config.changed_sections(&config)?;
+    let changed = |name: &str| changed_sections.iter().any(|section| section == name);
+    // The clock is picked by these two and can't be swapped under running loops
+    if changed("io_settings") {
+        bail!("Changing io_settings requires a restart");
+    }
+    if changed("storage_settings") {
+        bail!("Changing storage_settings requires a restart");
+    }
+    if program_state.config.simulation_settings.time_acceleration
+        != config.simulation_settings.time_acceleration
+    {
+        bail!("Changing time_acceleration requires a restart");
+    }
+    let zone_configs = config.resolve_zones();
+    let same_zones = zone_configs.len() == program_state.zones.len()
+        && zone_configs
+            .iter()
+            .zip(&program_state.zones)
+            .all(|((name, _), zone)| *name == zone.name);
+    if !same_zones {
+        bail!("Adding, removing or renaming zones requires a restart");
+    }
+    // The simulated grow buckets are set up for the relays and sensors of the zones
+    let reinit_io = changed("simulation_settings")
+        || changed("dimming_settings")
+        || changed("zones")
+        || program_state.config.relay_settings.relay_gpio_pins
+            != config.relay_settings.relay_gpio_pins
+        || program_state.config.relay_polarities() != config.relay_polarities();
+    let rewired = changed("relay_settings") || changed("devices") || changed("zones");
+    let pump_running = program_state
+        .zones
+        .iter()
+        .any(|zone| zone.pump_run.is_some());
+    if (reinit_io || rewired) && pump_running {
+        bail!("Can't change relay settings while the pump is running");
+    }
+    if rewired {
+        // The control loops renew their requests for the new pin assignment once woken, requests
+        // made by hand or by the watchdog stay for the relays that are still there
+        let pins = config
+            .relay_settings
+            .relay_gpio_pins
+            .iter()
+            .enumerate()
+            .filter(|(_, gpio_pin)| **gpio_pin != -1)
+            .map(|(pin, _)| pin as u8)
+            .collect::<Vec<_>>();
+        program_state
+            .arbiter
+            .retain(|pin, request| request.priority >= Priority::Manual && pins.contains(&pin));
+        actuators::switch_all_off(program_state, RequestSource::Config, "Relays rewired")?;
+    }
+    let reinit_result = match reinit_io {
+        true => reinit_io_with(program_state, &config),
+        false => Ok(()),
+    };
+    if reinit_result.is_ok() {
+        program_state.config = config;
+        for (zone, (_, config)) in program_state.zones.iter_mut().zip(zone_configs) {
+            zone.config = config;
+        }
+    }
+    // Also after a failed re-initialisation, so the relays don't stay off
+    if rewired {
+        actuators::file_startup_requests(program_state, None);
+    }
+    let reapplied = match reinit_io || rewired {
+        true => actuators::reapply_relay_requests(program_state),
+        false => Ok(()),
+    };
+    program_state.config_changed.notify_waiters();
+    reinit_result?;
+    reapplied?;
+    Ok(changed_sections)
+}
+
+/// Replaces the IO devices with ones set up for `config`, going back to the old ones on failure
+fn reinit_io_with(program_state: &mut ProgramState, config: &Configuration) -> anyhow::Result<()> {
+    actuators::switch_all_off(program_state, RequestSource::Config, "IO re-initialised")?;
+    // GPIO pins can only be claimed once, so the old devices have to go first
+    program_state.relay.release_bank();
+    program_state.dimmer = None;
+    let io = match io::init_io(config, program_state.clock.clone()) {
+        Ok(io) => io,
+        Err(e) => {
+            let old_io = io::init_io(&program_state.config, program_state.clock.clone())
+                .context("Could not restore the previous IO either")?;
+            install_io(program_state, old_io)?;
+            return Err(e.context("Could not initialise IO with the new settings"));
+        }
+    };
+    install_io(program_state, io)
+}
+
+fn install_io(program_state: &mut ProgramState, io: io::Io) -> anyhow::Result<()> {
+    program_state.relay.take_bank(io.relay);
+    actuators::switch_all_off(program_state, RequestSource::Config, "IO installed")?;
+    program_state.analog = io.analog;
+    program_state.dimmer = io.dimmer;
+    program_state.camera = io.camera;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{
+        clock::ScaledClock,
+        config::IoBackend,
+        io::RelaySwitchState,
+        testing::{TempDataDir, TestState},
+    };
+
+    #[test]
+    fn test_unreadable_history() {
+        let paths = TempDataDir::new("unreadable_history");
+        std::fs::write(
+            paths.history(crate::config::DEFAULT_ZONE),
+            "#growpi-timeseries v1\ntime,amount,moisture_before_watering,reason\nyesterday,1,0,Manual\n",
+        )
+        .unwrap();
+        let mut config = Configuration::default();
+        config.io_settings.backend = IoBackend::Simulated;
+        let clock = Arc::new(ScaledClock::new(Utc::now(), 1.));
+        assert!(init_state(config, clock, paths.clone()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_apply_config() {
+        let mut config = Configuration::default();
+        config.io_settings.backend = IoBackend::Simulated;
+        let program_state = TestState::new("apply_config", config.clone());
+        let mut program_state = program_state.lock().await;
+
+        let mut restart_needed = config.clone();
+        restart_needed.io_settings.backend = IoBackend::Hardware;
+        assert!(apply_config(&mut program_state, restart_needed).is_err());
+        let mut invalid = config.clone();
+        invalid.controller_settings.sunlight_hours = 25;
+        assert!(apply_config(&mut program_state, invalid).is_err());
+        let mut renamed = config.clone();
+        renamed.zones = vec![crate::config::ZoneSettings {
+            name: "left".to_string(),
+            light_pin: 0,
+            fan_pin: 1,
+            water_pump_pin: 2,
+            heater_pin: None,
+            thermistor_pin: 0,
+            soil_moisture_pin: 1,
+            active_profile: None,
+            controller_settings: None,
+        }];
+        assert!(apply_config(&mut program_state, renamed).is_err());
+
+        program_state.relay.switch(0, RelaySwitchState::On).unwrap();
+        let manual =
+            actuators::manual_request(RelaySwitchState::On, RequestSource::Http, &program_state);
+        actuators::request_relay(1, manual, &mut program_state).unwrap();
+        let mut changed = config.clone();
+        changed.controller_settings.sunlight_hours = 12;
+        changed.relay_settings.light_pin = 1;
+        changed.relay_settings.fan_pin = 0;
+        let changed_sections = apply_config(&mut program_state, changed).unwrap();
+        assert_eq!(changed_sections, ["controller_settings", "relay_settings"]);
+        assert_eq!(program_state.config.controller_settings.sunlight_hours, 12);
+        let zone_config = &program_state.zones[0].config;
+        assert_eq!(zone_config.controller_settings.sunlight_hours, 12);
+        // Relays are switched off until the control loops renew their requests
+        assert_eq!(
+            program_state.relay.get_state(0).unwrap(),
+            RelaySwitchState::Off
+        );
+        // Requests made by hand are kept
+        assert_eq!(
+            program_state.relay.get_state(1).unwrap(),
+            RelaySwitchState::On
+        );
+
+        // New relay GPIO pins are set up without a restart, safety handles keep working
+        let safety_handle = program_state.relay.safety_handle();
+        let mut rewired = program_state.config.clone();
+        rewired.relay_settings.relay_gpio_pins[3] = 23;
+        let changed_sections = apply_config(&mut program_state, rewired.clone()).unwrap();
+        assert_eq!(changed_sections, ["relay_settings"]);
+        assert_eq!(program_state.relay.configured_pins(), [0, 1, 2, 3]);
+        program_state.relay.switch(0, RelaySwitchState::On).unwrap();
+        assert_eq!(safety_handle.all_off().unwrap(), [0, 1]);
+        assert_eq!(
+            program_state.relay.get_state(0).unwrap(),
+            RelaySwitchState::Off
+        );
+    }
 }
@@ -0,0 +1,226 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::Storage;
+use crate::{
+    actuators::RelayEvent,
+    control::{
+        data_logging::{Aggregate, Bucket, BucketRecord, DataRecord, SensorRecord},
+        imaging::ImageRecord,
+    },
+    history::WateringRecord,
+    paths::Paths,
+    timeseries::TimeSeriesLog,
+};
+
+/// Keeps the records in the CSV files named by [`Paths`]
+pub struct CsvStorage {
+    paths: Paths,
+}
+
+impl CsvStorage {
+    pub fn new(paths: Paths) -> CsvStorage {
+        CsvStorage { paths }
+    }
+
+    /// Histories from before the time-series logs get their version marker on first use
+    fn history(&self, zone: &str) -> TimeSeriesLog<WateringRecord> {
+        TimeSeriesLog::new(self.paths.history(zone))
+    }
+}
+
+/// A [`BucketRecord`] with its aggregates spread over columns
+#[derive(Serialize, Deserialize)]
+struct RollupRow {
+    timestamp: i64,
+    samples: usize,
+    temperature_min: f32,
+    temperature_avg: f32,
+    temperature_max: f32,
+    soil_moisture_min: f32,
+    soil_moisture_avg: f32,
+    soil_moisture_max: f32,
+}
+
+impl From<&BucketRecord> for RollupRow {
+    fn from(record: &BucketRecord) -> RollupRow {
+        RollupRow {
+            timestamp: record.timestamp,
+            samples: record.samples,
+            temperature_min: record.temperature.min,
+            temperature_avg: record.temperature.avg,
+            temperature_max: record.temperature.max,
+            soil_moisture_min: record.soil_moisture.min,
+            soil_moisture_avg: record.soil_moisture.avg,
+            soil_moisture_max: record.soil_moisture.max,
+        }
+    }
+}
+
+impl From<RollupRow> for BucketRecord {
+    fn from(row: RollupRow) -> BucketRecord {
+        BucketRecord {
+            timestamp: row.timestamp,
+            samples: row.samples,
+            temperature: Aggregate {
+                min: row.temperature_min,
+                avg: row.temperature_avg,
+                max: row.temperature_max,
+            },
+            soil_moisture: Aggregate {
+                min: row.soil_moisture_min,
+                avg: row.soil_moisture_avg,
+                max: row.soil_moisture_max,
+            },
+        }
+    }
+}
+
+impl Storage for CsvStorage {
+    fn watering_records(&self, zone: &str) -> anyhow::Result<Vec<WateringRecord>> {
+        self.history(zone).upgrade_plain()?;
+        read_log(self.paths.history(zone))
+    }
+
+    fn add_watering_record(&mut self, zone: &str, record: &WateringRecord) -> anyhow::Result<()> {
+        let history = self.history(zone);
+        history.upgrade_plain()?;
+        history.append(std::slice::from_ref(record))
+    }
+
+    fn data_records(&self, zone: &str) -> anyhow::Result<Vec<DataRecord>> {
+        read_log(self.paths.datalog(zone))
+    }
+
+    fn add_data_record(&mut self, zone: &str, record: &DataRecord) -> anyhow::Result<()> {
+        TimeSeriesLog::new(self.paths.datalog(zone)).append(std::slice::from_ref(record))
+    }
+
+    fn rollups(
+        &self,
+        zone: &str,
+        bucket: Bucket,
+        from: i64,
+        to: i64,
+    ) -> anyhow::Result<Vec<BucketRecord>> {
+        let rows: Vec<RollupRow> = read_log(self.paths.datalog_rollups(zone, bucket))?;
+        Ok(rows
+            .into_iter()
+            .filter(|row| (from..=to).contains(&row.timestamp))
+            .map(BucketRecord::from)
+            .collect())
+    }
+
+    fn add_rollups(
+        &mut self,
+        zone: &str,
+        bucket: Bucket,
+        records: &[BucketRecord],
+    ) -> anyhow::Result<()> {
+        let rows = records.iter().map(RollupRow::from).collect::<Vec<_>>();
+        TimeSeriesLog::new(self.paths.datalog_rollups(zone, bucket)).append(&rows)
+    }
+
+    fn sensor_records(&self, zone: &str) -> anyhow::Result<Vec<SensorRecord>> {
+        read_log(self.paths.sensor_log(zone))
+    }
+
+    fn add_sensor_records(&mut self, zone: &str, records: &[SensorRecord]) -> anyhow::Result<()> {
+        TimeSeriesLog::new(self.paths.sensor_log(zone)).append(records)
+    }
+
+    fn images(&self) -> anyhow::Result<Vec<ImageRecord>> {
+        read_log(self.paths.image_log())
+    }
+
+    fn add_image(&mut self, record: &ImageRecord) -> anyhow::Result<()> {
+        TimeSeriesLog::new(self.paths.image_log()).append(std::slice::from_ref(record))
+    }
+
+    fn relay_events(&self, from: i64, to: i64) -> anyhow::Result<Vec<RelayEvent>> {
+        let mut events: Vec<RelayEvent> = read_log(self.paths.relay_events())?;
+        events.retain(|event| (from..=to).contains(&event.timestamp));
+        Ok(events)
+    }
+
+    fn add_relay_event(&mut self, event: &RelayEvent) -> anyhow::Result<()> {
+        TimeSeriesLog::new(self.paths.relay_events()).append(std::slice::from_ref(event))
+    }
+
+    fn prune_samples(&mut self, zone: &str, before: i64) -> anyhow::Result<()> {
+        TimeSeriesLog::<DataRecord>::new(self.paths.datalog(zone))
+            .retain(|record| record.timestamp >= before)?;
+        TimeSeriesLog::<SensorRecord>::new(self.paths.sensor_log(zone))
+            .retain(|record| record.timestamp >= before)?;
+        Ok(())
+    }
+
+    fn prune_rollups(&mut self, zone: &str, bucket: Bucket, before: i64) -> anyhow::Result<()> {
+        TimeSeriesLog::<RollupRow>::new(self.paths.datalog_rollups(zone, bucket))
+            .retain(|row| row.timestamp >= before)?;
+        Ok(())
+    }
+
+    fn prune_watering_records(&mut self, zone: &str, before: i64) -> anyhow::Result<()> {
+        let history = self.history(zone);
+        history.upgrade_plain()?;
+        history.retain(|record| record.time >= before)?;
+        Ok(())
+    }
+
+    fn prune_images(&mut self, before: i64) -> anyhow::Result<Vec<ImageRecord>> {
+        TimeSeriesLog::<ImageRecord>::new(self.paths.image_log())
+            .retain(|record| record.timestamp >= before)
+    }
+}
+
+fn read_log<T: Serialize + DeserializeOwned>(path: std::path::PathBuf) -> anyhow::Result<Vec<T>> {
+    match path.exists() {
+        true => TimeSeriesLog::new(path).read(),
+        false => Ok(Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::OpenOptions, io::Write};
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{history::WateringReason, testing::TempDataDir};
+
+    #[test]
+    fn test_history_cut_short() {
+        let paths = TempDataDir::new("csv_history");
+        // A history from before the version marker, with a power cut during the last append
+        std::fs::write(
+            paths.history("main"),
+            "time,amount,moisture_before_watering,reason\n100,250,0.4,Manual\n200,2",
+        )
+        .unwrap();
+        let mut storage = CsvStorage::new(paths.clone());
+
+        let records = storage.watering_records("main").unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].time, 100);
+        let record = WateringRecord::new(Utc::now(), 300, 0.3, WateringReason::Interval);
+        storage.add_watering_record("main", &record).unwrap();
+        let times = storage
+            .watering_records("main")
+            .unwrap()
+            .iter()
+            .map(|record| record.time)
+            .collect::<Vec<_>>();
+        assert_eq!(times, [100, record.time]);
+
+        // Cut short again after the upgrade
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(paths.history("main"))
+            .unwrap();
+        file.write_all(b"400,10").unwrap();
+        assert_eq!(storage.watering_records("main").unwrap().len(), 2);
+        storage.add_watering_record("main", &record).unwrap();
+        assert_eq!(storage.watering_records("main").unwrap().len(), 3);
+    }
+}
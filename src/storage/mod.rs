@@ -0,0 +1,181 @@
+//! Where watering records, logged samples and image metadata are kept
+//!
+//! Every backend holds the records of all zones, picked with `storage_settings.backend`.
+
+#[cfg(feature = "sqlite")]
+use anyhow::Context;
+
+use crate::{
+    actuators::RelayEvent,
+    config::{StorageBackend, StorageSettings},
+    control::{
+        data_logging::{Bucket, BucketRecord, DataRecord, SensorRecord},
+        imaging::ImageRecord,
+    },
+    history::WateringRecord,
+    paths::Paths,
+};
+
+mod csv_files;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use csv_files::CsvStorage;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStorage;
+
+pub trait Storage: Send {
+    /// Every watering of a zone, oldest first
+    fn watering_records(&self, zone: &str) -> anyhow::Result<Vec<WateringRecord>>;
+    fn add_watering_record(&mut self, zone: &str, record: &WateringRecord) -> anyhow::Result<()>;
+
+    /// Every temperature and soil moisture sample of a zone, oldest first
+    fn data_records(&self, zone: &str) -> anyhow::Result<Vec<DataRecord>>;
+    fn add_data_record(&mut self, zone: &str, record: &DataRecord) -> anyhow::Result<()>;
+
+    /// The temperature and soil moisture samples of a zone from `from` to `to`, both included
+    fn data_records_between(
+        &self,
+        zone: &str,
+        from: i64,
+        to: i64,
+    ) -> anyhow::Result<Vec<DataRecord>> {
+        let mut records = self.data_records(zone)?;
+        records.retain(|record| (from..=to).contains(&record.timestamp));
+        Ok(records)
+    }
+
+    /// The rollups of a zone per `bucket` starting from `from` to `to`, both included
+    fn rollups(
+        &self,
+        zone: &str,
+        bucket: Bucket,
+        from: i64,
+        to: i64,
+    ) -> anyhow::Result<Vec<BucketRecord>>;
+    fn add_rollups(
+        &mut self,
+        zone: &str,
+        bucket: Bucket,
+        records: &[BucketRecord],
+    ) -> anyhow::Result<()>;
+
+    /// Every sensor reading of a zone, oldest first
+    fn sensor_records(&self, zone: &str) -> anyhow::Result<Vec<SensorRecord>>;
+    fn add_sensor_records(&mut self, zone: &str, records: &[SensorRecord]) -> anyhow::Result<()>;
+
+    /// Every camera image taken, oldest first
+    fn images(&self) -> anyhow::Result<Vec<ImageRecord>>;
+    fn add_image(&mut self, record: &ImageRecord) -> anyhow::Result<()>;
+
+    /// The relay events from `from` to `to`, both included, oldest first
+    fn relay_events(&self, from: i64, to: i64) -> anyhow::Result<Vec<RelayEvent>>;
+    fn add_relay_event(&mut self, event: &RelayEvent) -> anyhow::Result<()>;
+
+    /// Removes the temperature, soil moisture and sensor samples of a zone from before `before`
+    fn prune_samples(&mut self, zone: &str, before: i64) -> anyhow::Result<()>;
+    fn prune_rollups(&mut self, zone: &str, bucket: Bucket, before: i64) -> anyhow::Result<()>;
+    fn prune_watering_records(&mut self, zone: &str, before: i64) -> anyhow::Result<()>;
+    /// Returns the images that were removed, so their files can go too
+    fn prune_images(&mut self, before: i64) -> anyhow::Result<Vec<ImageRecord>>;
+}
+
+/// Opens the storage picked in `settings`, holding the records of `zones`
+///
+/// An empty database is filled with the CSV files first, so switching to the SQLite backend
+/// keeps the watering history and the logs.
+#[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+pub fn open(
+    settings: &StorageSettings,
+    paths: &Paths,
+    zones: &[String],
+) -> anyhow::Result<Box<dyn Storage>> {
+    match settings.backend {
+        StorageBackend::Csv => Ok(Box::new(CsvStorage::new(paths.clone()))),
+        #[cfg(feature = "sqlite")]
+        StorageBackend::Sqlite => {
+            let mut storage = SqliteStorage::open(&paths.database())?;
+            if storage.is_empty()? {
+                let csv = CsvStorage::new(paths.clone());
+                let copied = storage
+                    .in_transaction(|storage| copy_records(&csv, storage, zones))
+                    .context("Could not import the CSV files into the database")?;
+                if copied > 0 {
+                    eprintln!(
+                        "Imported {} records from the CSV files into {}",
+                        copied,
+                        paths.database().display()
+                    );
+                }
+            }
+            Ok(Box::new(storage))
+        }
+        #[cfg(not(feature = "sqlite"))]
+        StorageBackend::Sqlite => anyhow::bail!("growpi was built without the sqlite feature"),
+    }
+}
+
+/// Copies every record of `zones` and the records shared by all zones, returning how many
+#[cfg(feature = "sqlite")]
+fn copy_records(
+    from: &dyn Storage,
+    to: &mut dyn Storage,
+    zones: &[String],
+) -> anyhow::Result<usize> {
+    let mut copied = 0;
+    for zone in zones {
+        for record in from.watering_records(zone)? {
+            to.add_watering_record(zone, &record)?;
+            copied += 1;
+        }
+        for record in from.data_records(zone)? {
+            to.add_data_record(zone, &record)?;
+            copied += 1;
+        }
+        for bucket in [Bucket::Hour, Bucket::Day] {
+            let rollups = from.rollups(zone, bucket, i64::MIN, i64::MAX)?;
+            to.add_rollups(zone, bucket, &rollups)?;
+            copied += rollups.len();
+        }
+        let sensor_records = from.sensor_records(zone)?;
+        to.add_sensor_records(zone, &sensor_records)?;
+        copied += sensor_records.len();
+    }
+    for record in from.images()? {
+        to.add_image(&record)?;
+        copied += 1;
+    }
+    for event in from.relay_events(i64::MIN, i64::MAX)? {
+        to.add_relay_event(&event)?;
+        copied += 1;
+    }
+    Ok(copied)
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{history::WateringReason, testing::TempDataDir};
+
+    #[test]
+    fn test_import_csv() {
+        let paths = TempDataDir::new("import_csv");
+        let record = WateringRecord::new(Utc::now(), 250, 0.4, WateringReason::Interval);
+        CsvStorage::new(paths.clone())
+            .add_watering_record("left", &record)
+            .unwrap();
+        let settings = StorageSettings {
+            backend: StorageBackend::Sqlite,
+        };
+        let zones = ["left".to_string()];
+
+        let storage = open(&settings, &paths, &zones).unwrap();
+        assert_eq!(storage.watering_records("left").unwrap().len(), 1);
+        drop(storage);
+        // Only an empty database is filled, so nothing is imported twice
+        let storage = open(&settings, &paths, &zones).unwrap();
+        assert_eq!(storage.watering_records("left").unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,582 @@
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use rusqlite::{params, Connection};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::Storage;
+use crate::{
+    actuators::RelayEvent,
+    control::{
+        data_logging::{Aggregate, Bucket, BucketRecord, DataRecord, SensorRecord},
+        imaging::ImageRecord,
+    },
+    history::WateringRecord,
+};
+
+/// Schema changes in the order they were made, `user_version` counts the ones already applied.
+/// Released migrations must never change, new ones are added at the end.
+const MIGRATIONS: &[&str] = &[
+    // 1: the tables for everything that used to be kept in CSV files, and relay events
+    "CREATE TABLE watering_records (
+        zone TEXT NOT NULL,
+        time INTEGER NOT NULL,
+        amount INTEGER NOT NULL,
+        moisture_before_watering REAL NOT NULL,
+        reason TEXT NOT NULL
+    );
+    CREATE INDEX watering_records_by_time ON watering_records (zone, time);
+    CREATE TABLE data_records (
+        zone TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        temperature REAL NOT NULL,
+        soil_moisture REAL NOT NULL
+    );
+    CREATE INDEX data_records_by_time ON data_records (zone, timestamp);
+    CREATE TABLE sensor_samples (
+        zone TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        sensor TEXT NOT NULL,
+        value REAL NOT NULL
+    );
+    CREATE INDEX sensor_samples_by_time ON sensor_samples (zone, timestamp);
+    CREATE TABLE relay_events (
+        timestamp INTEGER NOT NULL,
+        channel INTEGER NOT NULL,
+        device TEXT,
+        old_state TEXT NOT NULL,
+        new_state TEXT NOT NULL,
+        source TEXT NOT NULL
+    );
+    CREATE INDEX relay_events_by_time ON relay_events (timestamp);
+    CREATE TABLE images (
+        timestamp INTEGER NOT NULL,
+        path TEXT NOT NULL,
+        resolution TEXT NOT NULL,
+        size_bytes INTEGER NOT NULL
+    );",
+    // 2: hourly and daily rollups of the data records
+    "CREATE TABLE data_rollups (
+        zone TEXT NOT NULL,
+        bucket TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        samples INTEGER NOT NULL,
+        temperature_min REAL NOT NULL,
+        temperature_avg REAL NOT NULL,
+        temperature_max REAL NOT NULL,
+        soil_moisture_min REAL NOT NULL,
+        soil_moisture_avg REAL NOT NULL,
+        soil_moisture_max REAL NOT NULL,
+        PRIMARY KEY (zone, bucket, timestamp)
+    );
+    CREATE INDEX images_by_time ON images (timestamp);",
+    // 3: the zone of the device and the reason a relay switched
+    "ALTER TABLE relay_events ADD COLUMN zone TEXT;
+    ALTER TABLE relay_events ADD COLUMN reason TEXT NOT NULL DEFAULT '';",
+];
+
+/// Keeps the records of every zone in one SQLite database
+pub struct SqliteStorage {
+    connection: Connection,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &Path) -> anyhow::Result<SqliteStorage> {
+        let connection =
+            Connection::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+        // Commits survive a crash of the controller without an fsync per sample
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        SqliteStorage::with_connection(connection)
+            .with_context(|| format!("Could not migrate {}", path.display()))
+    }
+
+    fn with_connection(mut connection: Connection) -> anyhow::Result<SqliteStorage> {
+        migrate(&mut connection)?;
+        Ok(SqliteStorage { connection })
+    }
+
+    /// Whether no records were added yet
+    pub fn is_empty(&self) -> anyhow::Result<bool> {
+        let has_records: bool = self.connection.query_row(
+            "SELECT EXISTS (SELECT 1 FROM watering_records)
+                OR EXISTS (SELECT 1 FROM data_records)
+                OR EXISTS (SELECT 1 FROM data_rollups)
+                OR EXISTS (SELECT 1 FROM sensor_samples)
+                OR EXISTS (SELECT 1 FROM images)
+                OR EXISTS (SELECT 1 FROM relay_events)",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(!has_records)
+    }
+
+    /// Runs `f` in one transaction, which is rolled back when it fails
+    ///
+    /// The methods writing several rows use savepoints, so they can be part of it.
+    pub fn in_transaction<T>(
+        &mut self,
+        f: impl FnOnce(&mut SqliteStorage) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        self.connection.execute_batch("SAVEPOINT outer")?;
+        match f(self) {
+            Ok(value) => {
+                self.connection.execute_batch("RELEASE outer")?;
+                Ok(value)
+            }
+            Err(e) => {
+                self.connection
+                    .execute_batch("ROLLBACK TO outer; RELEASE outer")?;
+                Err(e)
+            }
+        }
+    }
+}
+
+fn migrate(connection: &mut Connection) -> anyhow::Result<()> {
+    let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        bail!(
+            "The database is at schema version {}, this version of growpi only knows {}",
+            version,
+            MIGRATIONS.len()
+        );
+    }
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", index + 1)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+impl Storage for SqliteStorage {
+    fn watering_records(&self, zone: &str) -> anyhow::Result<Vec<WateringRecord>> {
+        let mut statement = self.connection.prepare(
+            "SELECT time, amount, moisture_before_watering, reason FROM watering_records
+            WHERE zone = ?1 ORDER BY time",
+        )?;
+        let rows = statement.query_map([zone], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, f32>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+        rows.map(|row| {
+            let (time, amount, moisture_before_watering, reason) = row?;
+            Ok(WateringRecord {
+                time,
+                amount: amount as u64,
+                moisture_before_watering,
+                reason: from_text(reason)?,
+            })
+        })
+        .collect()
+    }
+
+    fn add_watering_record(&mut self, zone: &str, record: &WateringRecord) -> anyhow::Result<()> {
+        self.connection.execute(
+            "INSERT INTO watering_records (zone, time, amount, moisture_before_watering, reason)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                zone,
+                record.time,
+                record.amount as i64,
+                record.moisture_before_watering,
+                to_text(&record.reason)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn data_records(&self, zone: &str) -> anyhow::Result<Vec<DataRecord>> {
+        self.data_records_between(zone, i64::MIN, i64::MAX)
+    }
+
+    fn data_records_between(
+        &self,
+        zone: &str,
+        from: i64,
+        to: i64,
+    ) -> anyhow::Result<Vec<DataRecord>> {
+        let mut statement = self.connection.prepare(
+            "SELECT timestamp, temperature, soil_moisture FROM data_records
+            WHERE zone = ?1 AND timestamp BETWEEN ?2 AND ?3 ORDER BY timestamp",
+        )?;
+        let rows = statement.query_map(params![zone, from, to], |row| {
+            Ok(DataRecord {
+                timestamp: row.get(0)?,
+                temperature: row.get(1)?,
+                soil_mositure: row.get(2)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn add_data_record(&mut self, zone: &str, record: &DataRecord) -> anyhow::Result<()> {
+        self.connection.execute(
+            "INSERT INTO data_records (zone, timestamp, temperature, soil_moisture)
+            VALUES (?1, ?2, ?3, ?4)",
+            params![
+                zone,
+                record.timestamp,
+                record.temperature,
+                record.soil_mositure
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn rollups(
+        &self,
+        zone: &str,
+        bucket: Bucket,
+        from: i64,
+        to: i64,
+    ) -> anyhow::Result<Vec<BucketRecord>> {
+        let mut statement = self.connection.prepare(
+            "SELECT timestamp, samples, temperature_min, temperature_avg, temperature_max,
+                soil_moisture_min, soil_moisture_avg, soil_moisture_max
+            FROM data_rollups
+            WHERE zone = ?1 AND bucket = ?2 AND timestamp BETWEEN ?3 AND ?4 ORDER BY timestamp",
+        )?;
+        let rows = statement.query_map(params![zone, bucket.name(), from, to], |row| {
+            Ok(BucketRecord {
+                timestamp: row.get(0)?,
+                samples: row.get::<_, i64>(1)? as usize,
+                temperature: Aggregate {
+                    min: row.get(2)?,
+                    avg: row.get(3)?,
+                    max: row.get(4)?,
+                },
+                soil_moisture: Aggregate {
+                    min: row.get(5)?,
+                    avg: row.get(6)?,
+                    max: row.get(7)?,
+                },
+            })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn add_rollups(
+        &mut self,
+        zone: &str,
+        bucket: Bucket,
+        records: &[BucketRecord],
+    ) -> anyhow::Result<()> {
+        let transaction = self.connection.savepoint()?;
+        {
+            let mut statement = transaction.prepare(
+                "INSERT OR REPLACE INTO data_rollups (zone, bucket, timestamp, samples,
+                    temperature_min, temperature_avg, temperature_max,
+                    soil_moisture_min, soil_moisture_avg, soil_moisture_max)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            )?;
+            for record in records {
+                statement.execute(params![
+                    zone,
+                    bucket.name(),
+                    record.timestamp,
+                    record.samples as i64,
+                    record.temperature.min,
+                    record.temperature.avg,
+                    record.temperature.max,
+                    record.soil_moisture.min,
+                    record.soil_moisture.avg,
+                    record.soil_moisture.max
+                ])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn sensor_records(&self, zone: &str) -> anyhow::Result<Vec<SensorRecord>> {
+        let mut statement = self.connection.prepare(
+            "SELECT timestamp, sensor, value FROM sensor_samples
+            WHERE zone = ?1 ORDER BY timestamp",
+        )?;
+        let rows = statement.query_map([zone], |row| {
+            Ok(SensorRecord {
+                timestamp: row.get(0)?,
+                sensor: row.get(1)?,
+                value: row.get(2)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn add_sensor_records(&mut self, zone: &str, records: &[SensorRecord]) -> anyhow::Result<()> {
+        let transaction = self.connection.savepoint()?;
+        {
+            let mut statement = transaction.prepare(
+                "INSERT INTO sensor_samples (zone, timestamp, sensor, value)
+                VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for record in records {
+                statement.execute(params![zone, record.timestamp, record.sensor, record.value])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn images(&self) -> anyhow::Result<Vec<ImageRecord>> {
+        let mut statement = self.connection.prepare(
+            "SELECT timestamp, path, resolution, size_bytes FROM images ORDER BY timestamp",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+            ))
+        })?;
+        rows.map(|row| {
+            let (timestamp, path, resolution, size_bytes) = row?;
+            Ok(ImageRecord {
+                timestamp,
+                path,
+                resolution: from_text(resolution)?,
+                size_bytes: size_bytes as u64,
+            })
+        })
+        .collect()
+    }
+
+    fn add_image(&mut self, record: &ImageRecord) -> anyhow::Result<()> {
+        self.connection.execute(
+            "INSERT INTO images (timestamp, path, resolution, size_bytes) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                record.timestamp,
+                record.path,
+                to_text(&record.resolution)?,
+                record.size_bytes as i64
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn relay_events(&self, from: i64, to: i64) -> anyhow::Result<Vec<RelayEvent>> {
+        let mut statement = self.connection.prepare(
+            "SELECT timestamp, channel, zone, device, old_state, new_state, source, reason
+            FROM relay_events WHERE timestamp BETWEEN ?1 AND ?2 ORDER BY timestamp, rowid",
+        )?;
+        let rows = statement.query_map([from, to], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, u8>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, String>(5)?,
+                row.get::<_, String>(6)?,
+                row.get::<_, String>(7)?,
+            ))
+        })?;
+        rows.map(|row| {
+            let (timestamp, channel, zone, device, old_state, new_state, source, reason) = row?;
+            Ok(RelayEvent {
+                timestamp,
+                channel,
+                zone,
+                device,
+                old_state: from_text(old_state)?,
+                new_state: from_text(new_state)?,
+                source: from_text(source)?,
+                reason,
+            })
+        })
+        .collect()
+    }
+
+    fn add_relay_event(&mut self, event: &RelayEvent) -> anyhow::Result<()> {
+        self.connection.execute(
+            "INSERT INTO relay_events
+                (timestamp, channel, zone, device, old_state, new_state, source, reason)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                event.timestamp,
+                event.channel,
+                event.zone,
+                event.device,
+                to_text(&event.old_state)?,
+                to_text(&event.new_state)?,
+                to_text(&event.source)?,
+                event.reason
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn prune_samples(&mut self, zone: &str, before: i64) -> anyhow::Result<()> {
+        let transaction = self.connection.savepoint()?;
+        transaction.execute(
+            "DELETE FROM data_records WHERE zone = ?1 AND timestamp < ?2",
+            params![zone, before],
+        )?;
+        transaction.execute(
+            "DELETE FROM sensor_samples WHERE zone = ?1 AND timestamp < ?2",
+            params![zone, before],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn prune_rollups(&mut self, zone: &str, bucket: Bucket, before: i64) -> anyhow::Result<()> {
+        self.connection.execute(
+            "DELETE FROM data_rollups WHERE zone = ?1 AND bucket = ?2 AND timestamp < ?3",
+            params![zone, bucket.name(), before],
+        )?;
+        Ok(())
+    }
+
+    fn prune_watering_records(&mut self, zone: &str, before: i64) -> anyhow::Result<()> {
+        self.connection.execute(
+            "DELETE FROM watering_records WHERE zone = ?1 AND time < ?2",
+            params![zone, before],
+        )?;
+        Ok(())
+    }
+
+    fn prune_images(&mut self, before: i64) -> anyhow::Result<Vec<ImageRecord>> {
+        let removed = self
+            .images()?
+            .into_iter()
+            .filter(|record| record.timestamp < before)
+            .collect();
+        self.connection
+            .execute("DELETE FROM images WHERE timestamp < ?1", [before])?;
+        Ok(removed)
+    }
+}
+
+/// Stores an enum by the name of its variant, like in the CSV files
+fn to_text<T: Serialize>(value: &T) -> anyhow::Result<String> {
+    match serde_json::to_value(value)? {
+        serde_json::Value::String(text) => Ok(text),
+        other => bail!("{} can't be stored as text", other),
+    }
+}
+
+fn from_text<T: DeserializeOwned>(text: String) -> anyhow::Result<T> {
+    Ok(serde_json::from_value(serde_json::Value::String(text))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{
+        actuators::RequestSource,
+        history::WateringReason,
+        io::{ImageResolution, RelaySwitchState},
+    };
+
+    #[test]
+    fn test_round_trip() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        let mut storage =
+            SqliteStorage::with_connection(Connection::open_in_memory().unwrap()).unwrap();
+        // Migrating again leaves the schema as it is
+        migrate(&mut storage.connection).unwrap();
+
+        let record = WateringRecord::new(Utc::now(), 250, 0.4, WateringReason::Scheduled);
+        storage.add_watering_record("left", &record).unwrap();
+        let records = storage.watering_records("left").unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].time, record.time);
+        assert!(matches!(records[0].reason, WateringReason::Scheduled));
+        assert!(storage.watering_records("main").unwrap().is_empty());
+
+        let sample = |timestamp| DataRecord {
+            timestamp,
+            temperature: 24.5,
+            soil_mositure: 0.4,
+        };
+        storage.add_data_record("main", &sample(2)).unwrap();
+        storage.add_data_record("main", &sample(1)).unwrap();
+        let timestamps = storage
+            .data_records("main")
+            .unwrap()
+            .iter()
+            .map(|record| record.timestamp)
+            .collect::<Vec<_>>();
+        assert_eq!(timestamps, [1, 2]);
+        let between = storage.data_records_between("main", 2, 5).unwrap();
+        assert_eq!(between.len(), 1);
+
+        let readings = ["temperature", "light"].map(|sensor| SensorRecord {
+            timestamp: 1,
+            sensor: sensor.to_string(),
+            value: 1.,
+        });
+        storage.add_sensor_records("main", &readings).unwrap();
+        assert_eq!(storage.sensor_records("main").unwrap().len(), 2);
+
+        storage
+            .add_image(&ImageRecord {
+                timestamp: 1,
+                path: "growpi.image.jpeg".to_string(),
+                resolution: ImageResolution::R720p,
+                size_bytes: 1234,
+            })
+            .unwrap();
+        let images = storage.images().unwrap();
+        assert!(matches!(images[0].resolution, ImageResolution::R720p));
+
+        let rollup = BucketRecord {
+            timestamp: 3600,
+            samples: 2,
+            temperature: Aggregate {
+                min: 20.,
+                avg: 21.,
+                max: 22.,
+            },
+            soil_moisture: Aggregate {
+                min: 0.4,
+                avg: 0.4,
+                max: 0.4,
+            },
+        };
+        storage
+            .add_rollups("main", Bucket::Hour, std::slice::from_ref(&rollup))
+            .unwrap();
+        let rollups = storage.rollups("main", Bucket::Hour, 0, 3600).unwrap();
+        assert_eq!(rollups, [rollup]);
+        assert!(storage
+            .rollups("main", Bucket::Day, 0, 3600)
+            .unwrap()
+            .is_empty());
+        storage.prune_rollups("main", Bucket::Hour, 7200).unwrap();
+        storage.prune_samples("main", 2).unwrap();
+        assert_eq!(storage.data_records("main").unwrap().len(), 1);
+        assert_eq!(storage.prune_images(2).unwrap().len(), 1);
+        assert!(storage.images().unwrap().is_empty());
+
+        let event = RelayEvent {
+            timestamp: 5,
+            channel: 1,
+            zone: Some("main".to_string()),
+            device: Some("fan".to_string()),
+            old_state: RelaySwitchState::Off,
+            new_state: RelaySwitchState::On,
+            source: RequestSource::ClimateControl,
+            reason: "Too warm".to_string(),
+        };
+        storage.add_relay_event(&event).unwrap();
+        assert_eq!(storage.relay_events(0, 10).unwrap(), [event]);
+        assert!(storage.relay_events(6, 10).unwrap().is_empty());
+
+        // Databases from a newer version are left alone
+        connection
+            .pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        assert!(migrate(&mut connection).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+//! Setup shared by the tests that keep data files
+
+use std::{ops::Deref, sync::Arc};
+
+use chrono::Utc;
+
+use crate::{
+    clock::{ScaledClock, SharedClock},
+    config::{Configuration, IoBackend},
+    paths::Paths,
+    state::{init_state, ProgramStateShared},
+};
+
+/// An empty data directory of its own for a test, removed again when dropped
+pub struct TempDataDir {
+    paths: Paths,
+}
+
+impl TempDataDir {
+    /// Names have to differ between tests, since they run at the same time
+    pub fn new(name: &str) -> TempDataDir {
+        let paths = Paths {
+            data_dir: std::env::temp_dir().join(format!("growpi_test_{}", name)),
+            ..Paths::default()
+        };
+        let _ = std::fs::remove_dir_all(&paths.data_dir);
+        std::fs::create_dir_all(&paths.data_dir).unwrap();
+        TempDataDir { paths }
+    }
+}
+
+impl Deref for TempDataDir {
+    type Target = Paths;
+
+    fn deref(&self) -> &Paths {
+        &self.paths
+    }
+}
+
+impl Drop for TempDataDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.paths.data_dir);
+    }
+}
+
+/// Program state with simulated IO, keeping its files in a [`TempDataDir`]
+pub struct TestState {
+    // Dropped before the data directory is removed
+    program_state: ProgramStateShared,
+    pub paths: TempDataDir,
+}
+
+impl TestState {
+    pub fn new(name: &str, config: Configuration) -> TestState {
+        TestState::with_clock(name, config, Arc::new(ScaledClock::new(Utc::now(), 1.)))
+    }
+
+    pub fn with_clock(name: &str, mut config: Configuration, clock: SharedClock) -> TestState {
+        let paths = TempDataDir::new(name);
+        config.io_settings.backend = IoBackend::Simulated;
+        let program_state = init_state(config, clock, paths.clone()).unwrap();
+        TestState {
+            program_state,
+            paths,
+        }
+    }
+}
+
+impl Deref for TestState {
+    type Target = ProgramStateShared;
+
+    fn deref(&self) -> &ProgramStateShared {
+        &self.program_state
+    }
+}
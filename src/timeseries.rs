@@ -0,0 +1,252 @@
+//! Append-only CSV files for logged samples
+//!
+//! On-disk schema, version 1:
+//!
+//! ```text
+//! #growpi-timeseries v1
+//! timestamp,temperature,soil_mositure
+//! 1792317901,24.1,0.41
+//! 1792321501,24.6,0.39
+//! ```
+//!
+//! The first line marks the format version, the second holds the column names and every
+//! further line is one record, oldest first. Lines are only ever appended, each batch followed
+//! by an fsync. A new file is written in full under a temporary name and renamed into place, so
+//! it never exists without its header. A last line cut short by a power cut is dropped before
+//! the next append. Files with another version marker are moved aside instead of appended to.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    path::PathBuf,
+};
+
+use anyhow::{bail, Context};
+use serde::{de::DeserializeOwned, Serialize};
+
+pub const FORMAT_VERSION: u32 = 1;
+
+fn version_marker() -> String {
+    format!("#growpi-timeseries v{}", FORMAT_VERSION)
+}
+
+/// A log of records of type `T`, stored in the file at `path`
+pub struct TimeSeriesLog<T> {
+    path: PathBuf,
+    record: PhantomData<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> TimeSeriesLog<T> {
+    pub fn new(path: PathBuf) -> TimeSeriesLog<T> {
+        TimeSeriesLog {
+            path,
+            record: PhantomData,
+        }
+    }
+
+    /// Appends `records`, creating the file with its version marker and header first if needed
+    pub fn append(&self, records: &[T]) -> anyhow::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        if self.path.exists() && !self.has_current_version()? {
+            let old_path = self.path.with_extension("csv.old");
+            std::fs::rename(&self.path, &old_path)?;
+            eprintln!(
+                "{} was written by another version, moved it to {}",
+                self.path.display(),
+                old_path.display()
+            );
+        }
+        if !self.path.exists() {
+            return self.create(records);
+        }
+
+        self.drop_partial_line()?;
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(Vec::new());
+        for record in records {
+            writer.serialize(record)?;
+        }
+        let lines = writer.into_inner()?;
+        let mut file = OpenOptions::new().append(true).open(&self.path)?;
+        file.write_all(&lines)?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    /// Every record in the log, oldest first, leaving out a last line that was cut short
+    pub fn read(&self) -> anyhow::Result<Vec<T>> {
+        let text = self.read_complete_lines()?;
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .comment(Some(b'#'))
+            .from_reader(text.as_slice());
+        Ok(reader.deserialize::<T>().collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Adds the version marker to a plain CSV file with a header line, written before there
+    /// were time-series logs, keeping its records
+    pub fn upgrade_plain(&self) -> anyhow::Result<()> {
+        if !self.path.exists() || self.has_current_version()? {
+            return Ok(());
+        }
+        let text = self.read_complete_lines()?;
+        if text.starts_with(b"#") {
+            // Written by another version of the log, append moves it aside
+            return Ok(());
+        }
+        let records = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .from_reader(text.as_slice())
+            .deserialize::<T>()
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("Could not upgrade {}", self.path.display()))?;
+        match records.is_empty() {
+            true => std::fs::remove_file(&self.path)?,
+            false => self.create(&records)?,
+        }
+        eprintln!("Added a version marker to {}", self.path.display());
+        Ok(())
+    }
+
+    fn read_complete_lines(&self) -> anyhow::Result<Vec<u8>> {
+        let mut text = std::fs::read(&self.path)
+            .with_context(|| format!("Could not read {}", self.path.display()))?;
+        let complete = text
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map_or(0, |end| end + 1);
+        text.truncate(complete);
+        Ok(text)
+    }
+
+    /// Removes the records `keep` returns false for and returns them, the file goes once it
+    /// would be empty
+    pub fn retain(&self, keep: impl Fn(&T) -> bool) -> anyhow::Result<Vec<T>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        self.drop_partial_line()?;
+        let (kept, removed): (Vec<_>, Vec<_>) = self.read()?.into_iter().partition(keep);
+        if removed.is_empty() {
+            return Ok(removed);
+        }
+        match kept.is_empty() {
+            true => std::fs::remove_file(&self.path)?,
+            false => self.create(&kept)?,
+        }
+        Ok(removed)
+    }
+
+    fn create(&self, records: &[T]) -> anyhow::Result<()> {
+        let mut text = format!("{}\n", version_marker()).into_bytes();
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(true)
+            .from_writer(&mut text);
+        for record in records {
+            writer.serialize(record)?;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        let temp_path = self.path.with_extension("csv.tmp");
+        let mut file = File::create(&temp_path)?;
+        file.write_all(&text)?;
+        file.sync_all()?;
+        std::fs::rename(&temp_path, &self.path)?;
+        // The rename only survives a power cut once the directory is on disk too
+        if let Some(directory) = self.path.parent().filter(|dir| dir.is_dir()) {
+            File::open(directory)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    fn has_current_version(&self) -> anyhow::Result<bool> {
+        let mut first_line = String::new();
+        BufReader::new(File::open(&self.path)?).read_line(&mut first_line)?;
+        Ok(first_line.trim_end() == version_marker())
+    }
+
+    /// Cuts the file back to its last complete line
+    fn drop_partial_line(&self) -> anyhow::Result<()> {
+        let mut file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+        let len = file.metadata()?.len();
+        let tail_len = len.min(4096);
+        file.seek(SeekFrom::Start(len - tail_len))?;
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail)?;
+        if tail.last() == Some(&b'\n') {
+            return Ok(());
+        }
+        let Some(last_newline) = tail.iter().rposition(|byte| *byte == b'\n') else {
+            bail!("{} has a line longer than 4096 bytes", self.path.display());
+        };
+        file.set_len(len - tail_len + last_newline as u64 + 1)?;
+        file.sync_all()?;
+        eprintln!(
+            "Dropped an incomplete line at the end of {}",
+            self.path.display()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Sample {
+        timestamp: i64,
+        value: f32,
+    }
+
+    #[test]
+    fn test_append() {
+        let dir = std::env::temp_dir().join("growpi_test_timeseries");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("samples.csv");
+        let _ = std::fs::remove_file(&path);
+        let log = TimeSeriesLog::<Sample>::new(path.clone());
+        let sample = |timestamp| Sample {
+            timestamp,
+            value: 0.5,
+        };
+
+        log.append(&[sample(1)]).unwrap();
+        log.append(&[sample(2), sample(3)]).unwrap();
+        assert_eq!(log.read().unwrap(), [sample(1), sample(2), sample(3)]);
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text.matches("timestamp,value").count(), 1);
+        assert!(text.starts_with("#growpi-timeseries v1\n"));
+
+        // A power cut halfway through a line
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"4,0.").unwrap();
+        log.append(&[sample(5)]).unwrap();
+        assert_eq!(
+            log.read().unwrap(),
+            [sample(1), sample(2), sample(3), sample(5)]
+        );
+
+        // Files from before the version marker are moved aside
+        std::fs::write(&path, "timestamp,value\n1,0.5\n").unwrap();
+        log.append(&[sample(6)]).unwrap();
+        assert_eq!(log.read().unwrap(), [sample(6)]);
+        assert!(path.with_extension("csv.old").exists());
+
+        log.append(&[sample(7)]).unwrap();
+        let removed = log.retain(|sample| sample.timestamp > 6).unwrap();
+        assert_eq!(removed, [sample(6)]);
+        assert_eq!(log.read().unwrap(), [sample(7)]);
+        log.retain(|_| false).unwrap();
+        assert!(!path.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}